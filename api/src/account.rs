@@ -0,0 +1,263 @@
+//! TokenBondingV0 state: the newest `account_updates` point the consumer
+//! wrote, or the account read over RPC when the consumer has not seen it.
+
+use anchor_lang::AccountDeserialize;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use spl_token_bonding::state::TokenBondingV0;
+use std::fmt;
+use tracing::warn;
+
+use crate::flux::{FluxError, Row};
+use crate::hub::Update;
+use crate::schema::{orphaned_slots, ACCOUNT_UPDATES, NOT_ORPHANED, SCHEMA_VERSION};
+use crate::AppState;
+
+/// Pubkeys are base58 strings.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BondingAccount {
+    pub address: String,
+    /// Slot the state was read at.
+    pub slot: u64,
+    pub base_mint: String,
+    pub target_mint: String,
+    pub general_authority: Option<String>,
+    pub reserve_authority: Option<String>,
+    pub curve_authority: Option<String>,
+    pub base_storage: String,
+    pub buy_base_royalties: String,
+    pub buy_target_royalties: String,
+    pub sell_base_royalties: String,
+    pub sell_target_royalties: String,
+    pub buy_base_royalty_percentage: u32,
+    pub buy_target_royalty_percentage: u32,
+    pub sell_base_royalty_percentage: u32,
+    pub sell_target_royalty_percentage: u32,
+    pub curve: String,
+    pub mint_cap: Option<u64>,
+    pub purchase_cap: Option<u64>,
+    pub go_live_unix_time: i64,
+    pub freeze_buy_unix_time: Option<i64>,
+    pub created_at_unix_time: i64,
+    pub buy_frozen: bool,
+    pub sell_frozen: bool,
+    pub index: u16,
+    pub bump_seed: u8,
+    pub base_storage_bump_seed: u8,
+    pub target_mint_authority_bump_seed: u8,
+    pub base_storage_authority_bump_seed: Option<u8>,
+    pub reserve_balance_from_bonding: u64,
+    pub supply_from_bonding: u64,
+    pub ignore_external_reserve_changes: bool,
+    pub ignore_external_supply_changes: bool,
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    /// Nothing at the address, or not a TokenBondingV0.
+    NotBonding(Pubkey),
+    /// Neither InfluxDB nor RPC could be read.
+    Unavailable(String),
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::NotBonding(address) => write!(f, "{address} is not a TokenBondingV0 account"),
+            AccountError::Unavailable(reason) => write!(f, "account state unavailable: {reason}"),
+        }
+    }
+}
+
+/// Prefers the consumer's copy, which costs no RPC call; a failing InfluxDB
+/// only costs the fallback.
+pub async fn load(state: &AppState, address: &Pubkey) -> Result<BondingAccount, AccountError> {
+    match latest_update(state, address).await {
+        Ok(Some(account)) => return Ok(account),
+        Ok(None) => {}
+        Err(error) => warn!(%address, %error, "failed to read bonding from InfluxDB, trying RPC"),
+    }
+    fetch(state, address).await
+}
+
+async fn latest_update(state: &AppState, address: &Pubkey) -> Result<Option<BondingAccount>, FluxError> {
+    // `last()` reads only the newest value of every field, whatever the
+    // retention. Optional fields missing from the newest write come back with
+    // an older time, so pivoting splits them off into older rows, which the
+    // sort and limit drop. A newest state from a dead fork reads as none, so
+    // `load` falls back to RPC.
+    let query = format!(
+        r#"
+        {}
+        from(bucket:"{}")
+            |> range(start: 0)
+            |> filter(fn: (r) => r._measurement == "{}" and r.schema_version == "{}" and r.pubkey == "{}")
+            |> last()
+            |> pivot(rowKey:["_time"], columnKey: ["_field"], valueColumn: "_value")
+            |> group()
+            |> sort(columns: ["_time"], desc: true)
+            |> limit(n: 1)
+            {}
+        "#,
+        orphaned_slots(&state.bucket),
+        state.bucket,
+        ACCOUNT_UPDATES,
+        SCHEMA_VERSION,
+        address,
+        NOT_ORPHANED
+    );
+    state.flux.query(&query).await?.first().map(BondingAccount::from_row).transpose()
+}
+
+/// Filters of [`list`]; unset ones match everything.
+#[derive(Debug, Default)]
+pub struct ListFilter {
+    pub base_mint: Option<Pubkey>,
+    pub target_mint: Option<Pubkey>,
+}
+
+/// Latest state of every bonding the consumer has written, newest first.
+/// Accounts it never saw are not listed, nor those whose latest state is from
+/// a dead fork until they are written again.
+pub async fn list(state: &AppState, filter: &ListFilter, limit: usize) -> Result<Vec<BondingAccount>, FluxError> {
+    let mut predicate = format!(r#"r._measurement == "{ACCOUNT_UPDATES}" and r.schema_version == "{SCHEMA_VERSION}""#);
+    if let Some(base_mint) = filter.base_mint {
+        predicate.push_str(&format!(r#" and r.base_mint == "{base_mint}""#));
+    }
+    if let Some(target_mint) = filter.target_mint {
+        predicate.push_str(&format!(r#" and r.target_mint == "{target_mint}""#));
+    }
+    // As in `latest_update`, only the newest value of every field is read.
+    let query = format!(
+        r#"
+        {}
+        from(bucket:"{}")
+            |> range(start: 0)
+            |> filter(fn: (r) => {})
+            |> last()
+            |> pivot(rowKey:["_time"], columnKey: ["_field"], valueColumn: "_value")
+            |> group(columns: ["pubkey"])
+            |> sort(columns: ["_time"])
+            |> last(column: "_time")
+            {}
+            |> group()
+            |> sort(columns: ["_time"], desc: true)
+            |> limit(n: {})
+        "#,
+        orphaned_slots(&state.bucket),
+        state.bucket,
+        predicate,
+        NOT_ORPHANED,
+        limit
+    );
+    state.flux.query(&query).await?.iter().map(BondingAccount::from_row).collect()
+}
+
+async fn fetch(state: &AppState, address: &Pubkey) -> Result<BondingAccount, AccountError> {
+    let response = state
+        .rpc
+        .get_account_with_commitment(address, CommitmentConfig::confirmed())
+        .await
+        .map_err(|error| AccountError::Unavailable(error.to_string()))?;
+    let Some(account) = response.value else {
+        return Err(AccountError::NotBonding(*address));
+    };
+    // Checks the anchor discriminator, so other accounts of the program and
+    // accounts of other programs are both rejected.
+    let token_bonding = TokenBondingV0::try_deserialize(&mut account.data.as_slice())
+        .map_err(|_| AccountError::NotBonding(*address))?;
+    Ok(BondingAccount::from_token_bonding(address, response.context.slot, &token_bonding))
+}
+
+impl BondingAccount {
+    fn from_token_bonding(address: &Pubkey, slot: u64, token_bonding: &TokenBondingV0) -> Self {
+        Self {
+            address: address.to_string(),
+            slot,
+            base_mint: token_bonding.base_mint.to_string(),
+            target_mint: token_bonding.target_mint.to_string(),
+            general_authority: token_bonding.general_authority.map(|auth| auth.to_string()),
+            reserve_authority: token_bonding.reserve_authority.map(|auth| auth.to_string()),
+            curve_authority: token_bonding.curve_authority.map(|auth| auth.to_string()),
+            base_storage: token_bonding.base_storage.to_string(),
+            buy_base_royalties: token_bonding.buy_base_royalties.to_string(),
+            buy_target_royalties: token_bonding.buy_target_royalties.to_string(),
+            sell_base_royalties: token_bonding.sell_base_royalties.to_string(),
+            sell_target_royalties: token_bonding.sell_target_royalties.to_string(),
+            buy_base_royalty_percentage: token_bonding.buy_base_royalty_percentage,
+            buy_target_royalty_percentage: token_bonding.buy_target_royalty_percentage,
+            sell_base_royalty_percentage: token_bonding.sell_base_royalty_percentage,
+            sell_target_royalty_percentage: token_bonding.sell_target_royalty_percentage,
+            curve: token_bonding.curve.to_string(),
+            mint_cap: token_bonding.mint_cap,
+            purchase_cap: token_bonding.purchase_cap,
+            go_live_unix_time: token_bonding.go_live_unix_time,
+            freeze_buy_unix_time: token_bonding.freeze_buy_unix_time,
+            created_at_unix_time: token_bonding.created_at_unix_time,
+            buy_frozen: token_bonding.buy_frozen,
+            sell_frozen: token_bonding.sell_frozen,
+            index: token_bonding.index,
+            bump_seed: token_bonding.bump_seed,
+            base_storage_bump_seed: token_bonding.base_storage_bump_seed,
+            target_mint_authority_bump_seed: token_bonding.target_mint_authority_bump_seed,
+            base_storage_authority_bump_seed: token_bonding.base_storage_authority_bump_seed,
+            reserve_balance_from_bonding: token_bonding.reserve_balance_from_bonding,
+            supply_from_bonding: token_bonding.supply_from_bonding,
+            ignore_external_reserve_changes: token_bonding.ignore_external_reserve_changes,
+            ignore_external_supply_changes: token_bonding.ignore_external_supply_changes,
+        }
+    }
+
+    /// An `account_updates` point from the live feed. Tags and fields are
+    /// named like the columns of [`BondingAccount::from_row`].
+    pub fn from_update(update: &Update) -> serde_json::Result<Self> {
+        let mut object = update.fields.clone();
+        for (key, value) in &update.tags {
+            object.insert(key.clone(), value.clone().into());
+        }
+        if let Some(pubkey) = object.remove("pubkey") {
+            object.insert("address".to_string(), pubkey);
+        }
+        serde_json::from_value(object.into())
+    }
+
+    /// A pivoted `account_updates` row; see `account_update_point` in the
+    /// consumer for the columns.
+    fn from_row(row: &Row) -> Result<Self, FluxError> {
+        Ok(Self {
+            address: row.get("pubkey")?,
+            slot: row.get("slot")?,
+            base_mint: row.get("base_mint")?,
+            target_mint: row.get("target_mint")?,
+            general_authority: row.optional("general_authority")?,
+            reserve_authority: row.optional("reserve_authority")?,
+            curve_authority: row.optional("curve_authority")?,
+            base_storage: row.get("base_storage")?,
+            buy_base_royalties: row.get("buy_base_royalties")?,
+            buy_target_royalties: row.get("buy_target_royalties")?,
+            sell_base_royalties: row.get("sell_base_royalties")?,
+            sell_target_royalties: row.get("sell_target_royalties")?,
+            buy_base_royalty_percentage: row.get("buy_base_royalty_percentage")?,
+            buy_target_royalty_percentage: row.get("buy_target_royalty_percentage")?,
+            sell_base_royalty_percentage: row.get("sell_base_royalty_percentage")?,
+            sell_target_royalty_percentage: row.get("sell_target_royalty_percentage")?,
+            curve: row.get("curve")?,
+            mint_cap: row.optional("mint_cap")?,
+            purchase_cap: row.optional("purchase_cap")?,
+            go_live_unix_time: row.get("go_live_unix_time")?,
+            freeze_buy_unix_time: row.optional("freeze_buy_unix_time")?,
+            created_at_unix_time: row.get("created_at_unix_time")?,
+            buy_frozen: row.get("buy_frozen")?,
+            sell_frozen: row.get("sell_frozen")?,
+            index: row.get("index")?,
+            bump_seed: row.get("bump_seed")?,
+            base_storage_bump_seed: row.get("base_storage_bump_seed")?,
+            target_mint_authority_bump_seed: row.get("target_mint_authority_bump_seed")?,
+            base_storage_authority_bump_seed: row.optional("base_storage_authority_bump_seed")?,
+            reserve_balance_from_bonding: row.get("reserve_balance_from_bonding")?,
+            supply_from_bonding: row.get("supply_from_bonding")?,
+            ignore_external_reserve_changes: row.get("ignore_external_reserve_changes")?,
+            ignore_external_supply_changes: row.get("ignore_external_supply_changes")?,
+        })
+    }
+}
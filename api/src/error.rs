@@ -0,0 +1,85 @@
+//! Errors returned to clients, as JSON bodies over HTTP and as error frames
+//! over the websocket: `{"error": "<code>", "message": "<detail>"}`.
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use std::fmt;
+
+use crate::account::AccountError;
+use crate::flux::FluxError;
+
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    /// InfluxDB or RPC failed.
+    Unavailable(String),
+    /// Stored data could not be read.
+    Internal(String),
+    /// A websocket request named a method the protocol does not have.
+    UnknownMethod(String),
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Internal(_) => "internal",
+            ApiError::UnknownMethod(_) => "unknown_method",
+        }
+    }
+
+    pub fn body(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::NotFound(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Internal(message)
+            | ApiError::UnknownMethod(message) => f.write_str(message),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            // An unknown method is a malformed request, not a missing resource.
+            ApiError::BadRequest(_) | ApiError::UnknownMethod(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self.body())
+    }
+}
+
+impl From<AccountError> for ApiError {
+    fn from(error: AccountError) -> Self {
+        match error {
+            AccountError::NotBonding(_) => ApiError::NotFound(error.to_string()),
+            AccountError::Unavailable(_) => ApiError::Unavailable(error.to_string()),
+        }
+    }
+}
+
+impl From<FluxError> for ApiError {
+    fn from(error: FluxError) -> Self {
+        match error {
+            FluxError::Http(_) | FluxError::Query(_) => ApiError::Unavailable(error.to_string()),
+            FluxError::Parse { .. } | FluxError::Column { .. } => ApiError::Internal(error.to_string()),
+        }
+    }
+}
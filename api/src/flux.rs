@@ -0,0 +1,417 @@
+//! Flux queries against the InfluxDB 2 `/api/v2/query` endpoint.
+//!
+//! Results come back as annotated CSV: per table an optional `#datatype`,
+//! `#group` and `#default` annotation row, a header row and the data rows,
+//! with a blank line before every table whose columns differ from the
+//! previous one. [`FluxClient::query`] flattens all tables into [`Row`]s of
+//! typed [`Value`]s keyed by column name.
+
+use chrono::{DateTime, Utc};
+use std::{collections::HashMap, fmt};
+
+/// A cell converted according to its column's `#datatype`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// An empty cell without a `#default`.
+    Null,
+    String(String),
+    Bool(bool),
+    Long(i64),
+    UnsignedLong(u64),
+    Double(f64),
+    Time(DateTime<Utc>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::String(_) => "string",
+            Value::Bool(_) => "boolean",
+            Value::Long(_) => "long",
+            Value::UnsignedLong(_) => "unsignedLong",
+            Value::Double(_) => "double",
+            Value::Time(_) => "dateTime",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FluxError {
+    /// The request failed or InfluxDB answered with an error status.
+    Http(String),
+    /// The query ran and reported an error in its result.
+    Query(String),
+    /// The response is not valid annotated CSV.
+    Parse { line: usize, message: String },
+    /// A column is missing or holds a value of another type.
+    Column { column: String, message: String },
+}
+
+impl fmt::Display for FluxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FluxError::Http(message) => write!(f, "InfluxDB request failed: {message}"),
+            FluxError::Query(message) => write!(f, "Flux query failed: {message}"),
+            FluxError::Parse { line, message } => write!(f, "invalid Flux result at line {line}: {message}"),
+            FluxError::Column { column, message } => write!(f, "column {column}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for FluxError {}
+
+/// One record of a result table.
+#[derive(Debug, Clone, Default)]
+pub struct Row {
+    values: HashMap<String, Value>,
+}
+
+impl Row {
+    /// A required column; missing columns and nulls are errors.
+    pub fn get<T: FromValue>(&self, column: &str) -> Result<T, FluxError> {
+        self.optional(column)?.ok_or_else(|| FluxError::Column {
+            column: column.to_string(),
+            message: "missing".to_string(),
+        })
+    }
+
+    /// Fields left out of a write come back as nulls, or as a missing column
+    /// when no row of the table has them.
+    pub fn optional<T: FromValue>(&self, column: &str) -> Result<Option<T>, FluxError> {
+        match self.values.get(column) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => T::from_value(value).map(Some).map_err(|message| FluxError::Column {
+                column: column.to_string(),
+                message,
+            }),
+        }
+    }
+}
+
+/// Conversion from a cell. Integers convert between signednesses and widths
+/// when the value fits; nothing else is coerced.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, String>;
+}
+
+fn mismatch(expected: &str, value: &Value) -> String {
+    format!("expected {expected}, found {} {value:?}", value.type_name())
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::String(value) => Ok(value.clone()),
+            other => Err(mismatch("string", other)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Bool(value) => Ok(*value),
+            other => Err(mismatch("boolean", other)),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Double(value) => Ok(*value),
+            other => Err(mismatch("double", other)),
+        }
+    }
+}
+
+impl FromValue for DateTime<Utc> {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Time(value) => Ok(*value),
+            other => Err(mismatch("dateTime", other)),
+        }
+    }
+}
+
+macro_rules! integer_from_value {
+    ($($int:ty),*) => {$(
+        impl FromValue for $int {
+            fn from_value(value: &Value) -> Result<Self, String> {
+                let converted = match value {
+                    Value::Long(value) => <$int>::try_from(*value).ok(),
+                    Value::UnsignedLong(value) => <$int>::try_from(*value).ok(),
+                    other => return Err(mismatch(stringify!($int), other)),
+                };
+                converted.ok_or_else(|| format!("{value:?} does not fit in {}", stringify!($int)))
+            }
+        }
+    )*};
+}
+
+integer_from_value!(u8, u16, u32, u64, i64);
+
+/// Queries InfluxDB 2 over HTTP, authenticated with an API token.
+pub struct FluxClient {
+    http: reqwest::Client,
+    url: String,
+    org: String,
+    token: Option<String>,
+}
+
+impl FluxClient {
+    pub fn new(url: &str, org: String, token: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            org,
+            token,
+        }
+    }
+
+    pub async fn query(&self, flux: &str) -> Result<Vec<Row>, FluxError> {
+        let body = serde_json::json!({
+            "query": flux,
+            "type": "flux",
+            "dialect": { "annotations": ["datatype", "group", "default"] },
+        });
+        let mut request = self
+            .http
+            .post(format!("{}/api/v2/query", self.url))
+            .query(&[("org", self.org.as_str())])
+            .header("Accept", "application/csv")
+            .json(&body);
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Token {token}"));
+        }
+        let response = request.send().await.map_err(|error| FluxError::Http(error.to_string()))?;
+        let status = response.status();
+        let text = response.text().await.map_err(|error| FluxError::Http(error.to_string()))?;
+        if !status.is_success() {
+            return Err(FluxError::Http(format!("InfluxDB responded {status}: {}", text.trim())));
+        }
+        parse(&text)
+    }
+}
+
+/// Column layout of the table being read.
+struct Table {
+    columns: Vec<String>,
+    datatypes: Vec<String>,
+    defaults: Vec<String>,
+}
+
+/// Parses an annotated CSV result.
+pub fn parse(csv: &str) -> Result<Vec<Row>, FluxError> {
+    let mut rows = Vec::new();
+    let mut datatypes: Option<Vec<String>> = None;
+    let mut defaults: Option<Vec<String>> = None;
+    let mut table: Option<Table> = None;
+    for (line, record) in records(csv)? {
+        let parse_error = |message: String| FluxError::Parse { line, message };
+        if record.len() == 1 && record[0].is_empty() {
+            // A blank line: the next table brings its own annotations and
+            // header.
+            table = None;
+            continue;
+        }
+        match record[0].as_str() {
+            "#datatype" => {
+                datatypes = Some(record);
+                continue;
+            }
+            "#default" => {
+                defaults = Some(record);
+                continue;
+            }
+            annotation if annotation.starts_with('#') => continue,
+            _ => {}
+        }
+        let Some(current) = &table else {
+            table = Some(Table {
+                datatypes: datatypes.take().unwrap_or_default(),
+                defaults: defaults.take().unwrap_or_default(),
+                columns: record,
+            });
+            continue;
+        };
+        if record.len() != current.columns.len() {
+            return Err(parse_error(format!(
+                "{} cells for {} columns",
+                record.len(),
+                current.columns.len()
+            )));
+        }
+        let mut values = HashMap::with_capacity(record.len());
+        for (index, cell) in record.into_iter().enumerate().skip(1) {
+            let column = &current.columns[index];
+            let cell = if cell.is_empty() {
+                current.defaults.get(index).cloned().unwrap_or_default()
+            } else {
+                cell
+            };
+            let datatype = current.datatypes.get(index).map_or("string", String::as_str);
+            let value = convert(datatype, cell).map_err(|message| parse_error(format!("{column}: {message}")))?;
+            values.insert(column.clone(), value);
+        }
+        // Errors come as a table of their own: `,error,reference`.
+        if current.columns.get(1).map(String::as_str) == Some("error") {
+            let message = match values.get("error") {
+                Some(Value::String(message)) => message.clone(),
+                _ => "unknown error".to_string(),
+            };
+            return Err(FluxError::Query(message));
+        }
+        rows.push(Row { values });
+    }
+    Ok(rows)
+}
+
+fn convert(datatype: &str, cell: String) -> Result<Value, String> {
+    if cell.is_empty() {
+        return Ok(Value::Null);
+    }
+    let invalid = || format!("invalid {datatype} {cell:?}");
+    Ok(match datatype {
+        "boolean" => Value::Bool(cell.parse().map_err(|_| invalid())?),
+        "long" => Value::Long(cell.parse().map_err(|_| invalid())?),
+        "unsignedLong" => Value::UnsignedLong(cell.parse().map_err(|_| invalid())?),
+        "double" => Value::Double(match cell.as_str() {
+            "+Inf" => f64::INFINITY,
+            "-Inf" => f64::NEG_INFINITY,
+            _ => cell.parse().map_err(|_| invalid())?,
+        }),
+        datatype if datatype.starts_with("dateTime") => Value::Time(cell.parse().map_err(|_| invalid())?),
+        // string, duration, base64Binary
+        _ => Value::String(cell),
+    })
+}
+
+/// RFC 4180 records with the line each starts on. Quoted cells may hold
+/// commas, doubled quotes and line breaks; a blank line is one empty cell.
+fn records(csv: &str) -> Result<Vec<(usize, Vec<String>)>, FluxError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => {
+                if c == '\n' {
+                    line += 1;
+                }
+                cell.push(c);
+            }
+            (false, '"') if cell.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut cell)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut cell));
+                records.push((start, std::mem::take(&mut record)));
+                line += 1;
+                start = line;
+            }
+            (false, c) => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err(FluxError::Parse {
+            line: start,
+            message: "unterminated quoted cell".to_string(),
+        });
+    }
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push((start, record));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_typed_values_across_tables() {
+        let csv = "#datatype,string,long,dateTime:RFC3339,string,double,unsignedLong,boolean\r\n\
+                   #group,false,false,false,true,false,false,false\r\n\
+                   #default,_result,,,,,,\r\n\
+                   ,result,table,_time,pubkey,price,slot,buy\r\n\
+                   ,,0,2024-01-01T00:00:00.5Z,abc,1.25,7,true\r\n\
+                   ,,0,2024-01-01T00:00:01Z,abc,,8,false\r\n\
+                   \r\n\
+                   #datatype,string,long,string\r\n\
+                   #group,false,false,true\r\n\
+                   #default,_result,,\r\n\
+                   ,result,table,id\r\n\
+                   ,,1,sig:0\r\n";
+        let rows = parse(csv).unwrap();
+        assert_eq!(rows.len(), 3);
+
+        let first = &rows[0];
+        assert_eq!(first.get::<String>("result").unwrap(), "_result");
+        assert_eq!(first.get::<String>("pubkey").unwrap(), "abc");
+        assert_eq!(first.get::<f64>("price").unwrap(), 1.25);
+        assert_eq!(first.get::<u64>("slot").unwrap(), 7);
+        assert!(first.get::<bool>("buy").unwrap());
+        assert_eq!(
+            first.get::<DateTime<Utc>>("_time").unwrap(),
+            "2024-01-01T00:00:00.5Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        // An empty cell without a default is null.
+        assert_eq!(rows[1].optional::<f64>("price").unwrap(), None);
+        assert!(rows[1].get::<f64>("price").is_err());
+
+        // The second table brings its own columns.
+        assert_eq!(rows[2].get::<String>("id").unwrap(), "sig:0");
+        assert_eq!(rows[2].optional::<u64>("slot").unwrap(), None);
+    }
+
+    #[test]
+    fn quoted_cells_keep_commas_quotes_and_line_breaks() {
+        let csv = "#datatype,string,long,string\n,result,table,memo\n,,0,\"a, \"\"b\"\"\nc\"\n,,0,d\n";
+        let rows = parse(csv).unwrap();
+        assert_eq!(rows[0].get::<String>("memo").unwrap(), "a, \"b\"\nc");
+        assert_eq!(rows[1].get::<String>("memo").unwrap(), "d");
+    }
+
+    #[test]
+    fn error_tables_fail_the_query() {
+        let csv = "#datatype,string,string\n#group,true,true\n#default,,\n,error,reference\n,bucket not found,\n";
+        match parse(csv) {
+            Err(FluxError::Query(message)) => assert_eq!(message, "bucket not found"),
+            other => panic!("expected a query error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn malformed_results_report_their_line() {
+        let csv = "#datatype,string,long\n,result,table\n,,0\n,,0,extra\n";
+        assert!(matches!(parse(csv), Err(FluxError::Parse { line: 4, .. })));
+
+        let csv = "#datatype,string,long\n,result,table\n,,zero\n";
+        assert!(matches!(parse(csv), Err(FluxError::Parse { line: 3, .. })));
+
+        assert!(matches!(parse(",result,\"open\n"), Err(FluxError::Parse { line: 1, .. })));
+    }
+
+    #[test]
+    fn integers_convert_only_when_they_fit() {
+        assert_eq!(u8::from_value(&Value::Long(255)), Ok(255));
+        assert!(u8::from_value(&Value::Long(256)).is_err());
+        assert!(u64::from_value(&Value::Long(-1)).is_err());
+        assert_eq!(i64::from_value(&Value::UnsignedLong(5)), Ok(5));
+        assert!(i64::from_value(&Value::Double(5.0)).is_err());
+        assert!(String::from_value(&Value::Long(5)).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! Trades of one bonding, as written by the consumer to `bonding_trades`.
+//! Trades of slots the consumer marked as orphaned are left out.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::fmt;
+
+use crate::flux::{FluxError, Row};
+use crate::hub::Update;
+use crate::schema::{orphaned_slots, BONDING_TRADES, NOT_ORPHANED, SCHEMA_VERSION};
+use crate::AppState;
+
+/// A window of one bonding's history; `start` and `stop` are RFC 3339
+/// times. REST routes take the address from the path instead.
+#[derive(Deserialize)]
+pub struct BondingRequest {
+    #[serde(default)]
+    pub address: String,
+    #[serde(alias = "start")]
+    pub start_unix_time: DateTime<Utc>,
+    #[serde(alias = "stop")]
+    pub stop_unix_time: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct BondingChange {
+    pub reserve_change: f64,
+    pub supply_change: f64,
+    pub insert_ts: i64,
+}
+
+/// One buy or sell; amounts are in whole tokens.
+#[derive(Serialize)]
+pub struct Trade {
+    pub id: String,
+    pub time: DateTime<Utc>,
+    pub signature: String,
+    pub slot: u64,
+    pub side: String,
+    pub trader: String,
+    pub reserve_change: f64,
+    pub supply_change: f64,
+    pub price: Option<f64>,
+    pub base_royalties_paid: f64,
+    pub target_royalties_paid: f64,
+}
+
+/// A page of trades, newest first. `next_cursor` fetches the following,
+/// older page and is absent on the last one.
+#[derive(Serialize)]
+pub struct TradePage {
+    pub trades: Vec<Trade>,
+    pub next_cursor: Option<String>,
+}
+
+/// Reserve and supply changes of every trade in `[start, stop)`, oldest
+/// first.
+pub async fn bonding_changes(
+    state: &AppState,
+    address: &Pubkey,
+    start: DateTime<Utc>,
+    stop: DateTime<Utc>,
+) -> Result<Vec<BondingChange>, FluxError> {
+    let query = format!(
+        r#"
+        {}
+        from(bucket:"{}")
+            |> range(start: {}, stop: {})
+            |> filter(fn: (r) => r._measurement == "{}" and r.schema_version == "{}" and r.pubkey == "{}")
+            |> filter(fn: (r) => r._field == "reserve_change" or r._field == "supply_change" or r._field == "slot")
+            |> pivot(rowKey:["_time"], columnKey: ["_field"], valueColumn: "_value")
+            {}
+            |> group()
+            |> sort(columns: ["_time"])
+            |> keep(columns: ["_time", "reserve_change", "supply_change"])
+        "#,
+        orphaned_slots(&state.bucket),
+        state.bucket,
+        start.timestamp(),
+        stop.timestamp(),
+        BONDING_TRADES,
+        SCHEMA_VERSION,
+        address,
+        NOT_ORPHANED
+    );
+    state.flux.query(&query).await?.iter().map(BondingChange::from_row).collect()
+}
+
+/// Position of a trade in the newest-first order of [`trades`]: its time,
+/// then its point ID to order trades sharing a timestamp.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeCursor {
+    pub time: DateTime<Utc>,
+    pub id: String,
+}
+
+impl TradeCursor {
+    fn of(trade: &Trade) -> Self {
+        Self {
+            time: trade.time,
+            id: trade.id.clone(),
+        }
+    }
+
+    /// Neither RFC 3339 times nor point IDs contain `_`. IDs are checked to
+    /// be `<signature>:<index>[.<inner>]` before they are spliced into Flux.
+    pub fn parse(cursor: &str) -> Option<Self> {
+        let (time, id) = cursor.split_once('_')?;
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == ':' || c == '.') {
+            return None;
+        }
+        Some(Self {
+            time: time.parse().ok()?,
+            id: id.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for TradeCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.time.to_rfc3339_opts(SecondsFormat::Nanos, true), self.id)
+    }
+}
+
+/// Up to `limit` trades after `cursor` in newest-first order, or the newest
+/// ones without it.
+///
+/// Trades are ordered by time and then point ID, and the cursor holds both,
+/// so a page ending inside a group of trades with the same timestamp neither
+/// skips nor repeats any of them.
+pub async fn trades(
+    state: &AppState,
+    address: &Pubkey,
+    cursor: Option<&TradeCursor>,
+    limit: usize,
+) -> Result<TradePage, FluxError> {
+    let (stop, after_cursor) = match cursor {
+        Some(cursor) => {
+            let time = format!(r#"time(v: "{}")"#, cursor.time.to_rfc3339_opts(SecondsFormat::Nanos, true));
+            // The range stop is exclusive; the filter drops the cursor's own
+            // trade and those ordered before it.
+            let filter = format!(r#"|> filter(fn: (r) => r._time < {time} or r.id < "{}")"#, cursor.id);
+            (format!("date.add(d: 1ns, to: {time})"), filter)
+        }
+        None => ("now()".to_string(), String::new()),
+    };
+    // One extra row tells whether there is a next page.
+    let query = format!(
+        r#"
+        import "date"
+        {}
+        from(bucket:"{}")
+            |> range(start: 0, stop: {})
+            |> filter(fn: (r) => r._measurement == "{}" and r.schema_version == "{}" and r.pubkey == "{}")
+            |> pivot(rowKey:["_time"], columnKey: ["_field"], valueColumn: "_value")
+            {}
+            {}
+            |> group()
+            |> sort(columns: ["_time", "id"], desc: true)
+            |> limit(n: {})
+        "#,
+        orphaned_slots(&state.bucket),
+        state.bucket,
+        stop,
+        BONDING_TRADES,
+        SCHEMA_VERSION,
+        address,
+        NOT_ORPHANED,
+        after_cursor,
+        limit + 1
+    );
+    let mut trades = state
+        .flux
+        .query(&query)
+        .await?
+        .iter()
+        .map(Trade::from_row)
+        .collect::<Result<Vec<_>, _>>()?;
+    let next_cursor = if trades.len() > limit {
+        trades.truncate(limit);
+        trades.last().map(|trade| TradeCursor::of(trade).to_string())
+    } else {
+        None
+    };
+    Ok(TradePage { trades, next_cursor })
+}
+
+impl BondingChange {
+    pub fn from_row(row: &Row) -> Result<Self, FluxError> {
+        Ok(Self {
+            reserve_change: row.get("reserve_change")?,
+            supply_change: row.get("supply_change")?,
+            insert_ts: row.get::<DateTime<Utc>>("_time")?.timestamp(),
+        })
+    }
+
+    /// A `bonding_trades` point from the live feed.
+    pub fn from_update(update: &Update) -> serde_json::Result<Self> {
+        let field = |name: &str| {
+            update
+                .fields
+                .get(name)
+                .and_then(serde_json::Value::as_f64)
+                .ok_or_else(|| serde::de::Error::custom(format!("missing field {name}")))
+        };
+        Ok(Self {
+            reserve_change: field("reserve_change")?,
+            supply_change: field("supply_change")?,
+            insert_ts: update.time.timestamp(),
+        })
+    }
+}
+
+impl Trade {
+    /// A pivoted `bonding_trades` row; see `trade_point` in the consumer for
+    /// the columns.
+    fn from_row(row: &Row) -> Result<Self, FluxError> {
+        Ok(Self {
+            id: row.get("id")?,
+            time: row.get("_time")?,
+            signature: row.get("signature")?,
+            slot: row.get("slot")?,
+            side: row.get("side")?,
+            trader: row.get("trader")?,
+            reserve_change: row.get("reserve_change")?,
+            supply_change: row.get("supply_change")?,
+            price: row.optional("price")?,
+            base_royalties_paid: row.get("base_royalties_paid")?,
+            target_royalties_paid: row.get("target_royalties_paid")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = TradeCursor {
+            time: "2024-05-01T12:00:00.123456789Z".parse().unwrap(),
+            id: "5VERv8NMvzbJMEkV8xnrLkEaWRtSz9CosKDYjCJjBRnbJLgp8uirBgmQpjKhoR4tjF3ZpRzrFmBV6UjKdiSZkQUW:3.1".to_string(),
+        };
+        assert_eq!(TradeCursor::parse(&cursor.to_string()), Some(cursor));
+    }
+
+    #[test]
+    fn cursor_rejects_ids_that_would_escape_the_query() {
+        assert_eq!(TradeCursor::parse(r#"2024-05-01T12:00:00Z_sig") or true or ("#), None);
+        assert_eq!(TradeCursor::parse("2024-05-01T12:00:00Z_"), None);
+        assert_eq!(TradeCursor::parse("2024-05-01T12:00:00Z"), None);
+    }
+}
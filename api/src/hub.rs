@@ -0,0 +1,237 @@
+//! Live updates for websocket clients.
+//!
+//! One AMQP consumer per process reads every point the consumer's AMQP sink
+//! publishes (`bonding.#` on its topic exchange) and fans it out over a
+//! broadcast channel, so the number of connected clients does not change the
+//! load on RabbitMQ or InfluxDB. Points published while the feed is
+//! reconnecting are not replayed; clients catch up with a history request.
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use lapin::{
+    options::{BasicConsumeOptions, ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions},
+    types::FieldTable,
+    Connection, ConnectionProperties, ExchangeKind,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+/// Updates a slow client may fall behind by before it misses some.
+const CHANNEL_CAPACITY: usize = 4096;
+/// Message IDs remembered to drop the consumer's retried publishes.
+const RECENT_IDS: usize = 10_000;
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// A point as published by the consumer, see `consumer/src/point.rs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Update {
+    pub measurement: String,
+    #[serde(default)]
+    pub id: String,
+    pub time: DateTime<Utc>,
+    pub tags: BTreeMap<String, String>,
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Update {
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AmqpConfig {
+    pub url: String,
+    /// Exchange the consumer's AMQP sink publishes to.
+    pub exchange: String,
+}
+
+/// Cheap to clone; every clone feeds the same subscribers.
+#[derive(Clone)]
+pub struct Hub {
+    sender: broadcast::Sender<Arc<Update>>,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl Hub {
+    /// Every update published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Update>> {
+        self.sender.subscribe()
+    }
+
+    /// Feeds the hub from RabbitMQ until the process exits, reconnecting
+    /// with backoff.
+    pub async fn run(self, config: AmqpConfig) {
+        let mut delay = Duration::from_secs(1);
+        let mut recent = RecentIds::default();
+        loop {
+            match self.consume(&config, &mut recent, &mut delay).await {
+                Ok(()) => warn!(?delay, "amqp feed closed, reconnecting"),
+                Err(error) => error!(?delay, %error, "amqp feed failed, reconnecting"),
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    async fn consume(&self, config: &AmqpConfig, recent: &mut RecentIds, delay: &mut Duration) -> lapin::Result<()> {
+        let connection = Connection::connect(&config.url, ConnectionProperties::default()).await?;
+        let channel = connection.create_channel().await?;
+        // Same arguments as the consumer, so whichever starts first creates it.
+        channel
+            .exchange_declare(
+                &config.exchange,
+                ExchangeKind::Topic,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        // A private queue that disappears with the connection.
+        let queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        channel
+            .queue_bind(
+                queue.name().as_str(),
+                &config.exchange,
+                "bonding.#",
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        let mut deliveries = channel
+            .basic_consume(
+                queue.name().as_str(),
+                "bonding-api",
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        info!(exchange = %config.exchange, "amqp feed connected");
+        *delay = Duration::from_secs(1);
+
+        while let Some(delivery) = deliveries.next().await {
+            let delivery = delivery?;
+            let update: Update = match serde_json::from_slice(&delivery.data) {
+                Ok(update) => update,
+                Err(error) => {
+                    warn!(%error, "skipping undecodable amqp message");
+                    continue;
+                }
+            };
+            if !recent.insert(format!("{}:{}", update.measurement, update.id)) {
+                continue;
+            }
+            // No subscribers is not an error.
+            let _ = self.sender.send(Arc::new(update));
+        }
+        Ok(())
+    }
+}
+
+/// Bounded set of recently seen message IDs, oldest evicted first.
+#[derive(Default)]
+struct RecentIds {
+    order: VecDeque<String>,
+    seen: HashSet<String>,
+}
+
+impl RecentIds {
+    /// Returns whether `id` was new. Points without an ID (`<measurement>:`)
+    /// are always new.
+    fn insert(&mut self, id: String) -> bool {
+        if id.ends_with(':') {
+            return true;
+        }
+        if !self.seen.insert(id.clone()) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > RECENT_IDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_ids_drop_repeats() {
+        let mut recent = RecentIds::default();
+        assert!(recent.insert("bonding_trades:sig:0".to_string()));
+        assert!(!recent.insert("bonding_trades:sig:0".to_string()));
+        assert!(recent.insert("bonding_events:sig:0".to_string()));
+        // No ID to dedupe on.
+        assert!(recent.insert("bonding_trades:".to_string()));
+        assert!(recent.insert("bonding_trades:".to_string()));
+    }
+
+    #[test]
+    fn recent_ids_forget_the_oldest_first() {
+        let mut recent = RecentIds::default();
+        for i in 0..=RECENT_IDS {
+            assert!(recent.insert(format!("slots:{i}")));
+        }
+        assert_eq!(recent.order.len(), RECENT_IDS);
+        assert!(recent.insert("slots:0".to_string()));
+        assert!(!recent.insert(format!("slots:{RECENT_IDS}")));
+    }
+
+    #[test]
+    fn decodes_points_published_by_the_consumer() {
+        let body = r#"{
+            "measurement": "bonding_trades",
+            "id": "sig:0",
+            "time": "2023-11-14T22:13:20.000000005Z",
+            "tags": {"pubkey": "Bonding111", "side": "sell", "target_mint": "Mint111"},
+            "fields": {"price": 0.5, "reserve_change_raw": -1000, "slot": 42, "trader": "Wallet111"}
+        }"#;
+        let update: Update = serde_json::from_str(body).unwrap();
+        assert_eq!(update.measurement, "bonding_trades");
+        assert_eq!(update.id, "sig:0");
+        assert_eq!(update.time.timestamp(), 1_700_000_000);
+        assert_eq!(update.time.timestamp_subsec_nanos(), 5);
+        assert_eq!(update.tag("side"), Some("sell"));
+        assert_eq!(update.tag("base_mint"), None);
+        assert_eq!(update.fields["price"], 0.5);
+        assert_eq!(update.fields["reserve_change_raw"], -1000);
+        assert_eq!(update.fields["trader"], "Wallet111");
+    }
+
+    #[test]
+    fn rejects_messages_that_are_not_points() {
+        assert!(serde_json::from_str::<Update>(r#"{"measurement": "bonding_trades"}"#).is_err());
+        assert!(serde_json::from_str::<Update>("[]").is_err());
+    }
+}
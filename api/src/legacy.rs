@@ -0,0 +1,139 @@
+//! `/ws/{address}`: the per-bonding socket that predates [`crate::session`],
+//! kept frame for frame for its existing clients.
+//!
+//! On connect the last day of reserve and supply changes goes out as one JSON
+//! array. `{"type": "getAccountInfo"}` is answered with the bare bonding
+//! state, or `{"type": "error", "request": "getAccountInfo", ...}`; other
+//! requests are ignored. Trades and account updates of the address are
+//! pushed in the shapes of the history and of `getAccountInfo`.
+
+use actix::prelude::*;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use chrono::{Duration, Utc};
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+use crate::account::{self, BondingAccount};
+use crate::error::ApiError;
+use crate::history::{self, BondingChange};
+use crate::hub::Update;
+use crate::rest::parse_pubkey;
+use crate::schema::{ACCOUNT_UPDATES, BONDING_TRADES};
+use crate::AppState;
+
+/// History sent on connect.
+const HISTORY_ON_CONNECT_HOURS: i64 = 24;
+
+pub struct LegacySession {
+    address: Pubkey,
+    state: web::Data<AppState>,
+}
+
+pub async fn connect(
+    req: HttpRequest,
+    path: web::Path<String>,
+    stream: web::Payload,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let address = parse_pubkey("address", &path)?;
+    ws::start(LegacySession { address, state }, &req, stream)
+}
+
+impl LegacySession {
+    fn handle_request(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let Ok(request) = serde_json::from_str::<Value>(text) else {
+            return;
+        };
+        match request["type"].as_str() {
+            Some("getAccountInfo") => {
+                let (state, address) = (self.state.clone(), self.address);
+                let load = async move { account::load(&state, &address).await };
+                ctx.spawn(actix::fut::wrap_future::<_, Self>(load).map(|result, _, ctx| match result {
+                    Ok(account) => ctx.text(serde_json::json!(account).to_string()),
+                    Err(error) => ctx.text(error_frame("getAccountInfo", &error.into()).to_string()),
+                }));
+            }
+            Some(request_type) => debug!(request_type, "ignoring unknown legacy request type"),
+            None => {}
+        }
+    }
+}
+
+impl Actor for LegacySession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let (state, address) = (self.state.clone(), self.address);
+        let history = async move {
+            let stop = Utc::now();
+            let start = stop - Duration::hours(HISTORY_ON_CONNECT_HOURS);
+            history::bonding_changes(&state, &address, start, stop)
+                .await
+                .unwrap_or_else(|error| {
+                    warn!(%address, %error, "failed to query history");
+                    Vec::new()
+                })
+        };
+        ctx.spawn(actix::fut::wrap_future::<_, Self>(history).map(|changes, _, ctx| {
+            ctx.text(serde_json::json!(changes).to_string());
+        }));
+
+        // Tied to the actor, so the subscription ends with the connection.
+        let updates = futures::stream::unfold(self.state.hub.subscribe(), |mut updates| async move {
+            loop {
+                match updates.recv().await {
+                    Ok(update) => return Some((update, updates)),
+                    Err(RecvError::Lagged(skipped)) => warn!(skipped, "websocket fell behind, skipping updates"),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        ctx.add_stream(updates);
+    }
+}
+
+impl StreamHandler<Arc<Update>> for LegacySession {
+    fn handle(&mut self, update: Arc<Update>, ctx: &mut Self::Context) {
+        if update.tag("pubkey") != Some(self.address.to_string().as_str()) {
+            return;
+        }
+        let frame = match update.measurement.as_str() {
+            BONDING_TRADES => BondingChange::from_update(&update).map(|change| serde_json::json!(change)),
+            ACCOUNT_UPDATES => BondingAccount::from_update(&update).map(|account| serde_json::json!(account)),
+            _ => return,
+        };
+        match frame {
+            Ok(frame) => ctx.text(frame.to_string()),
+            Err(error) => warn!(measurement = %update.measurement, %error, "skipping malformed update"),
+        }
+    }
+
+    /// The hub outlives every connection; keep the socket open regardless.
+    fn finished(&mut self, _ctx: &mut Self::Context) {}
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for LegacySession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Text(text)) => self.handle_request(&text, ctx),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Sent in place of a response when a request fails.
+fn error_frame(request: &str, error: &ApiError) -> Value {
+    let mut frame = error.body();
+    frame["type"] = "error".into();
+    frame["request"] = request.into();
+    frame
+}
@@ -0,0 +1,99 @@
+//! REST routes under `/bondings`. Every error body has the shape described
+//! in [`crate::error`].
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+
+use crate::account::{self, ListFilter};
+use crate::error::ApiError;
+use crate::history::{self, BondingRequest, TradeCursor};
+use crate::AppState;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .app_data(web::QueryConfig::default().error_handler(|error, _| ApiError::BadRequest(error.to_string()).into()))
+        .app_data(web::PathConfig::default().error_handler(|error, _| ApiError::BadRequest(error.to_string()).into()))
+        .route("/bondings", web::get().to(list_bondings))
+        .route("/bondings/{address}", web::get().to(get_bonding))
+        .route("/bondings/{address}/history", web::get().to(get_history))
+        .route("/bondings/{address}/trades", web::get().to(get_trades));
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    base_mint: Option<String>,
+    target_mint: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct TradesQuery {
+    /// `next_cursor` of the previous page.
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Bondings the consumer has seen, optionally narrowed by mint.
+async fn list_bondings(query: web::Query<ListQuery>, state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let filter = ListFilter {
+        base_mint: query.base_mint.as_deref().map(|mint| parse_pubkey("base_mint", mint)).transpose()?,
+        target_mint: query.target_mint.as_deref().map(|mint| parse_pubkey("target_mint", mint)).transpose()?,
+    };
+    let bondings = account::list(&state, &filter, limit(query.limit)?).await?;
+    Ok(HttpResponse::Ok().json(bondings))
+}
+
+/// Current TokenBondingV0 state; 404 when the address holds none.
+async fn get_bonding(path: web::Path<String>, state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let address = parse_pubkey("address", &path)?;
+    let account = account::load(&state, &address).await?;
+    Ok(HttpResponse::Ok().json(account))
+}
+
+/// Reserve and supply changes between `start` and `stop`.
+async fn get_history(
+    path: web::Path<String>,
+    query: web::Query<BondingRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let address = parse_pubkey("address", &path)?;
+    if query.start_unix_time >= query.stop_unix_time {
+        return Err(ApiError::BadRequest("start must be before stop".to_string()));
+    }
+    let changes =
+        history::bonding_changes(&state, &address, query.start_unix_time, query.stop_unix_time).await?;
+    Ok(HttpResponse::Ok().json(changes))
+}
+
+/// Trades newest first, a page at a time.
+async fn get_trades(
+    path: web::Path<String>,
+    query: web::Query<TradesQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let address = parse_pubkey("address", &path)?;
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| TradeCursor::parse(cursor).ok_or_else(|| ApiError::BadRequest(format!("invalid cursor {cursor:?}"))))
+        .transpose()?;
+    let page = history::trades(&state, &address, cursor.as_ref(), limit(query.limit)?).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Addresses are checked before they are spliced into Flux.
+pub(crate) fn parse_pubkey(name: &str, value: &str) -> Result<Pubkey, ApiError> {
+    Pubkey::from_str(value).map_err(|_| ApiError::BadRequest(format!("{name} {value:?} is not a valid pubkey")))
+}
+
+fn limit(limit: Option<usize>) -> Result<usize, ApiError> {
+    match limit.unwrap_or(DEFAULT_LIMIT) {
+        limit @ 1..=MAX_LIMIT => Ok(limit),
+        _ => Err(ApiError::BadRequest(format!("limit must be between 1 and {MAX_LIMIT}"))),
+    }
+}
@@ -0,0 +1,23 @@
+//! InfluxDB schema read by the API. Mirrors `consumer/src/schema.rs`, which
+//! owns the layout; bump `SCHEMA_VERSION` in both crates together.
+
+/// Points written under any other version are ignored.
+pub const SCHEMA_VERSION: &str = "3";
+
+pub const ACCOUNT_UPDATES: &str = "account_updates";
+pub const BONDING_TRADES: &str = "bonding_trades";
+pub const ORPHANED_SLOTS: &str = "orphaned_slots";
+
+/// Flux statement binding `orphaned` to the slots the consumer marked as on a
+/// dead fork. Pair with [`NOT_ORPHANED`] after a pivot that kept `slot`.
+pub fn orphaned_slots(bucket: &str) -> String {
+    format!(
+        r#"orphaned = from(bucket:"{bucket}")
+            |> range(start: 0)
+            |> filter(fn: (r) => r._measurement == "{ORPHANED_SLOTS}" and r.schema_version == "{SCHEMA_VERSION}" and r._field == "slot")
+            |> findColumn(fn: (key) => true, column: "_value")"#
+    )
+}
+
+/// Drops pivoted rows written for an orphaned slot.
+pub const NOT_ORPHANED: &str = "|> filter(fn: (r) => not contains(value: r.slot, set: orphaned))";
@@ -0,0 +1,526 @@
+//! Websocket protocol, version 1.
+//!
+//! Requests are `{"version": 1, "id": <any>, "method": "...", "params": {...}}`,
+//! where `version` defaults to 1 and `id` to null. Every request gets exactly
+//! one frame back carrying its `id`: `{"version": 1, "id": <id>, "result": ...}`
+//! or, on failure, the error body of [`crate::error`] plus `version` and `id`.
+//!
+//! - `subscribe` / `unsubscribe`, params `{"accounts": [...], "mints": [...]}`:
+//!   the result lists every account and mint subscribed afterwards.
+//! - `getHistory`, params a [`BondingRequest`]: reserve and supply changes.
+//! - `getAccountInfo`, params `{"address": ...}`: the current bonding state.
+//!
+//! Trades and account updates of subscribed bondings, and of bondings of
+//! subscribed base or target mints, arrive as notifications without an `id`:
+//! `{"version": 1, "method": "trade" | "account", "params": ...}`.
+//!
+//! The older per-bonding socket on `/ws/{address}` is served unchanged by
+//! [`crate::legacy`].
+
+use actix::prelude::*;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use chrono::{DateTime, Utc};
+use futures::future::{FutureExt, LocalBoxFuture};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::account::{self, BondingAccount};
+use crate::error::ApiError;
+use crate::history::{self, BondingChange, BondingRequest};
+use crate::hub::Update;
+use crate::rest::parse_pubkey;
+use crate::schema::{ACCOUNT_UPDATES, BONDING_TRADES};
+use crate::AppState;
+
+pub const PROTOCOL_VERSION: u64 = 1;
+/// Accounts and mints together, per connection.
+const MAX_SUBSCRIPTIONS: usize = 1000;
+
+#[derive(Deserialize)]
+struct Request {
+    version: Option<u64>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Subscription {
+    #[serde(default)]
+    accounts: Vec<String>,
+    #[serde(default)]
+    mints: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct AccountParams {
+    address: String,
+}
+
+/// How a well-formed request is answered.
+enum Reply {
+    Now(Result<Value, ApiError>),
+    /// Needs InfluxDB or RPC; answered once the future completes.
+    Later(LocalBoxFuture<'static, Result<Value, ApiError>>),
+}
+
+/// One websocket connection and what it is subscribed to, as base58 strings
+/// so they compare directly against the tags of hub updates.
+pub struct Session {
+    state: web::Data<AppState>,
+    accounts: BTreeSet<String>,
+    mints: BTreeSet<String>,
+}
+
+/// `/ws`: a session without subscriptions.
+pub async fn connect(
+    req: HttpRequest,
+    stream: web::Payload,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session = Session {
+        state,
+        accounts: BTreeSet::new(),
+        mints: BTreeSet::new(),
+    };
+    ws::start(session, &req, stream)
+}
+
+impl Session {
+    fn handle_request(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let (id, request) = parse_request(text);
+        match request.map(|request| self.call(request)) {
+            Ok(Reply::Now(result)) => ctx.text(response(&id, result)),
+            Ok(Reply::Later(future)) => self.respond_later(id, ctx, future),
+            Err(error) => ctx.text(response(&id, Err(error))),
+        }
+    }
+
+    fn call(&mut self, request: Request) -> Reply {
+        match request.method.as_str() {
+            "subscribe" => Reply::Now(params(request.params).and_then(|subscription| self.subscribe(subscription))),
+            "unsubscribe" => {
+                Reply::Now(params(request.params).and_then(|subscription| self.unsubscribe(subscription)))
+            }
+            "getHistory" => match params(request.params).and_then(history_window) {
+                Ok((address, start, stop)) => {
+                    let state = self.state.clone();
+                    Reply::Later(
+                        async move {
+                            let changes = history::bonding_changes(&state, &address, start, stop).await?;
+                            Ok(json!(changes))
+                        }
+                        .boxed_local(),
+                    )
+                }
+                Err(error) => Reply::Now(Err(error)),
+            },
+            "getAccountInfo" => {
+                match params::<AccountParams>(request.params)
+                    .and_then(|params| parse_pubkey("address", &params.address))
+                {
+                    Ok(address) => {
+                        let state = self.state.clone();
+                        Reply::Later(
+                            async move {
+                                let account = account::load(&state, &address).await?;
+                                Ok(json!(account))
+                            }
+                            .boxed_local(),
+                        )
+                    }
+                    Err(error) => Reply::Now(Err(error)),
+                }
+            }
+            method => Reply::Now(Err(ApiError::UnknownMethod(format!("unknown method {method:?}")))),
+        }
+    }
+
+    /// Validates every key before subscribing to any, so a rejected request
+    /// changes nothing.
+    fn subscribe(&mut self, subscription: Subscription) -> Result<Value, ApiError> {
+        let (accounts, mints) = parse_subscription(&subscription)?;
+        let added = accounts.difference(&self.accounts).count() + mints.difference(&self.mints).count();
+        if self.accounts.len() + self.mints.len() + added > MAX_SUBSCRIPTIONS {
+            return Err(ApiError::BadRequest(format!(
+                "at most {MAX_SUBSCRIPTIONS} accounts and mints per connection"
+            )));
+        }
+        self.accounts.extend(accounts);
+        self.mints.extend(mints);
+        Ok(self.subscriptions())
+    }
+
+    /// Keys that were not subscribed are ignored.
+    fn unsubscribe(&mut self, subscription: Subscription) -> Result<Value, ApiError> {
+        let (accounts, mints) = parse_subscription(&subscription)?;
+        self.accounts.retain(|account| !accounts.contains(account));
+        self.mints.retain(|mint| !mints.contains(mint));
+        Ok(self.subscriptions())
+    }
+
+    fn subscriptions(&self) -> Value {
+        json!({ "accounts": self.accounts, "mints": self.mints })
+    }
+
+    fn wants(&self, update: &Update) -> bool {
+        let tagged = |key, keys: &BTreeSet<String>| update.tag(key).map_or(false, |value| keys.contains(value));
+        tagged("pubkey", &self.accounts) || tagged("base_mint", &self.mints) || tagged("target_mint", &self.mints)
+    }
+
+    /// Answers `id` once `future` completes; requests are not ordered among
+    /// each other.
+    fn respond_later(
+        &self,
+        id: Value,
+        ctx: &mut ws::WebsocketContext<Self>,
+        future: LocalBoxFuture<'static, Result<Value, ApiError>>,
+    ) {
+        ctx.spawn(actix::fut::wrap_future::<_, Self>(future).map(move |result, _, ctx| {
+            ctx.text(response(&id, result));
+        }));
+    }
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // Tied to the actor, so the subscription ends with the connection.
+        let updates = futures::stream::unfold(self.state.hub.subscribe(), |mut updates| async move {
+            loop {
+                match updates.recv().await {
+                    Ok(update) => return Some((update, updates)),
+                    Err(RecvError::Lagged(skipped)) => warn!(skipped, "websocket fell behind, skipping updates"),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        ctx.add_stream(updates);
+    }
+}
+
+impl StreamHandler<Arc<Update>> for Session {
+    fn handle(&mut self, update: Arc<Update>, ctx: &mut Self::Context) {
+        if !self.wants(&update) {
+            return;
+        }
+        let notification = match update.measurement.as_str() {
+            BONDING_TRADES => BondingChange::from_update(&update).map(|change| {
+                let mut params = json!(change);
+                params["address"] = update.tag("pubkey").into();
+                ("trade", params)
+            }),
+            ACCOUNT_UPDATES => BondingAccount::from_update(&update).map(|account| ("account", json!(account))),
+            _ => return,
+        };
+        match notification {
+            Ok((method, params)) => ctx.text(
+                json!({
+                    "version": PROTOCOL_VERSION,
+                    "method": method,
+                    "params": params,
+                })
+                .to_string(),
+            ),
+            Err(error) => warn!(measurement = %update.measurement, %error, "skipping malformed update"),
+        }
+    }
+
+    /// The hub outlives every connection; keep the socket open regardless.
+    fn finished(&mut self, _ctx: &mut Self::Context) {}
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Text(text)) => self.handle_request(&text, ctx),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The request's `id`, read on its own so that even a malformed request is
+/// answered with it, and the request or why it was rejected.
+fn parse_request(text: &str) -> (Value, Result<Request, ApiError>) {
+    let request = match serde_json::from_str::<Value>(text) {
+        Ok(request) => request,
+        Err(error) => {
+            return (Value::Null, Err(ApiError::BadRequest(format!("request is not JSON: {error}"))));
+        }
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let request = match serde_json::from_value::<Request>(request) {
+        Ok(request) => request,
+        Err(error) => return (id, Err(ApiError::BadRequest(format!("invalid request: {error}")))),
+    };
+    if let Some(version) = request.version.filter(|version| *version != PROTOCOL_VERSION) {
+        let error = ApiError::BadRequest(format!(
+            "unsupported protocol version {version}, expected {PROTOCOL_VERSION}"
+        ));
+        return (id, Err(error));
+    }
+    (id, Ok(request))
+}
+
+/// Missing params read as `{}`.
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, ApiError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|error| ApiError::BadRequest(format!("invalid params: {error}")))
+}
+
+/// The same checks as the REST history route.
+fn history_window(request: BondingRequest) -> Result<(Pubkey, DateTime<Utc>, DateTime<Utc>), ApiError> {
+    let address = parse_pubkey("address", &request.address)?;
+    if request.start_unix_time >= request.stop_unix_time {
+        return Err(ApiError::BadRequest("start must be before stop".to_string()));
+    }
+    Ok((address, request.start_unix_time, request.stop_unix_time))
+}
+
+fn parse_subscription(subscription: &Subscription) -> Result<(BTreeSet<String>, BTreeSet<String>), ApiError> {
+    let parse = |name, keys: &[String]| {
+        keys.iter()
+            .map(|key| parse_pubkey(name, key).map(|key| key.to_string()))
+            .collect::<Result<BTreeSet<_>, _>>()
+    };
+    Ok((parse("account", subscription.accounts.as_slice())?, parse("mint", subscription.mints.as_slice())?))
+}
+
+fn response(id: &Value, result: Result<Value, ApiError>) -> String {
+    match result {
+        Ok(result) => json!({
+            "version": PROTOCOL_VERSION,
+            "id": id,
+            "result": result,
+        })
+        .to_string(),
+        Err(error) => error_frame(id, &error).to_string(),
+    }
+}
+
+/// Sent in place of a result when a request fails.
+fn error_frame(id: &Value, error: &ApiError) -> Value {
+    let mut frame = error.body();
+    frame["version"] = PROTOCOL_VERSION.into();
+    frame["id"] = id.clone();
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flux::FluxClient;
+    use crate::hub::Hub;
+    use solana_client::nonblocking::rpc_client::RpcClient;
+
+    fn session() -> Session {
+        let state = AppState {
+            flux: FluxClient::new("http://localhost:8086", "org".to_string(), None),
+            bucket: "bucket".to_string(),
+            rpc: RpcClient::new("http://localhost:8899".to_string()),
+            hub: Hub::default(),
+        };
+        Session {
+            state: web::Data::new(state),
+            accounts: BTreeSet::new(),
+            mints: BTreeSet::new(),
+        }
+    }
+
+    fn key(seed: u8) -> String {
+        Pubkey::new_from_array([seed; 32]).to_string()
+    }
+
+    fn subscription(accounts: &[String], mints: &[String]) -> Subscription {
+        Subscription {
+            accounts: accounts.to_vec(),
+            mints: mints.to_vec(),
+        }
+    }
+
+    fn update(tags: &[(&str, String)]) -> Update {
+        Update {
+            measurement: BONDING_TRADES.to_string(),
+            id: "sig:0".to_string(),
+            time: Utc::now(),
+            tags: tags.iter().map(|(key, value)| (key.to_string(), value.clone())).collect(),
+            fields: serde_json::Map::new(),
+        }
+    }
+
+    #[test]
+    fn wants_subscribed_bondings_and_mints_on_either_side() {
+        let mut session = session();
+        session.subscribe(subscription(&[key(1)], &[key(2)])).unwrap();
+
+        assert!(session.wants(&update(&[("pubkey", key(1))])));
+        assert!(session.wants(&update(&[("pubkey", key(9)), ("base_mint", key(2))])));
+        assert!(session.wants(&update(&[("pubkey", key(9)), ("target_mint", key(2))])));
+        assert!(!session.wants(&update(&[("pubkey", key(9)), ("base_mint", key(8))])));
+        // Mints and accounts are not interchangeable.
+        assert!(!session.wants(&update(&[("pubkey", key(2))])));
+        assert!(!session.wants(&update(&[("target_mint", key(1))])));
+        assert!(!session.wants(&update(&[])));
+    }
+
+    #[test]
+    fn subscriptions_are_normalized_pubkeys() {
+        let (accounts, mints) = parse_subscription(&subscription(&[key(1), key(1)], &[key(2)])).unwrap();
+        assert_eq!(accounts, BTreeSet::from([key(1)]));
+        assert_eq!(mints, BTreeSet::from([key(2)]));
+
+        let error = parse_subscription(&subscription(&[key(1)], &["not-a-key".to_string()])).unwrap_err();
+        assert!(matches!(error, ApiError::BadRequest(_)), "{error:?}");
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe_report_what_is_left() {
+        let mut session = session();
+        let result = session.subscribe(subscription(&[key(1), key(2)], &[key(3)])).unwrap();
+        assert_eq!(result, json!({ "accounts": [key(1), key(2)], "mints": [key(3)] }));
+
+        // Keys that were never subscribed are ignored.
+        let result = session.unsubscribe(subscription(&[key(2), key(4)], &[])).unwrap();
+        assert_eq!(result, json!({ "accounts": [key(1)], "mints": [key(3)] }));
+    }
+
+    #[test]
+    fn subscriptions_are_capped_per_connection() {
+        let mut session = session();
+        let keys: Vec<String> = (0..MAX_SUBSCRIPTIONS as u32)
+            .map(|i| {
+                let mut bytes = [0u8; 32];
+                bytes[..4].copy_from_slice(&i.to_le_bytes());
+                Pubkey::new_from_array(bytes).to_string()
+            })
+            .collect();
+        let (accounts, mints) = keys.split_at(MAX_SUBSCRIPTIONS - 1);
+        session.subscribe(subscription(accounts, &[])).unwrap();
+        // Resubscribing to a key already held adds nothing.
+        session.subscribe(subscription(&accounts[..1], &[])).unwrap();
+        session.subscribe(subscription(&[], mints)).unwrap();
+
+        let extra = key(255);
+        let error = session.subscribe(subscription(&[extra.clone()], &[])).unwrap_err();
+        assert!(matches!(error, ApiError::BadRequest(_)), "{error:?}");
+        // A rejected request changes nothing.
+        assert_eq!(session.accounts.len() + session.mints.len(), MAX_SUBSCRIPTIONS);
+
+        // Unsubscribing frees room again.
+        session.unsubscribe(subscription(&[], mints)).unwrap();
+        session.subscribe(subscription(&[extra], &[])).unwrap();
+        assert_eq!(session.accounts.len(), MAX_SUBSCRIPTIONS);
+    }
+
+    /// The frame a request is answered with right away; `None` when it is
+    /// answered later.
+    fn answer(session: &mut Session, request: Value) -> Option<Value> {
+        let (id, request) = parse_request(&request.to_string());
+        let frame = match request.map(|request| session.call(request)) {
+            Ok(Reply::Now(result)) => response(&id, result),
+            Ok(Reply::Later(_)) => return None,
+            Err(error) => response(&id, Err(error)),
+        };
+        Some(serde_json::from_str(&frame).unwrap())
+    }
+
+    fn error_code(frame: &Value) -> &str {
+        frame["error"].as_str().unwrap_or_default()
+    }
+
+    #[test]
+    fn subscribe_frames_carry_the_id_and_result() {
+        let mut session = session();
+        let frame = answer(
+            &mut session,
+            json!({"id": "a", "method": "subscribe", "params": {"accounts": [key(1)]}}),
+        );
+        assert_eq!(
+            frame,
+            Some(json!({"version": 1, "id": "a", "result": {"accounts": [key(1)], "mints": []}}))
+        );
+
+        let frame = answer(&mut session, json!({"id": 2, "method": "subscribe", "params": {"wallets": []}})).unwrap();
+        assert_eq!((frame["version"].clone(), frame["id"].clone()), (json!(1), json!(2)));
+        assert_eq!(error_code(&frame), "bad_request");
+        assert!(frame.get("result").is_none());
+    }
+
+    #[test]
+    fn unsubscribe_frames_carry_the_id_and_result() {
+        let mut session = session();
+        answer(&mut session, json!({"method": "subscribe", "params": {"mints": [key(2)]}}));
+        let frame = answer(&mut session, json!({"id": 3, "method": "unsubscribe", "params": {"mints": [key(2)]}}));
+        assert_eq!(
+            frame,
+            Some(json!({"version": 1, "id": 3, "result": {"accounts": [], "mints": []}}))
+        );
+
+        let frame = answer(&mut session, json!({"id": 4, "method": "unsubscribe", "params": {"mints": ["x"]}}));
+        assert_eq!(frame.as_ref().map(error_code), Some("bad_request"));
+        assert_eq!(frame.unwrap()["id"], 4);
+    }
+
+    #[test]
+    fn get_history_validates_before_querying() {
+        let mut session = session();
+        let window = |start: &str, stop: &str| {
+            json!({"id": 5, "method": "getHistory", "params": {"address": key(1), "start": start, "stop": stop}})
+        };
+        assert!(answer(&mut session, window("2024-05-01T00:00:00Z", "2024-05-02T00:00:00Z")).is_none());
+
+        let frame = answer(&mut session, window("2024-05-02T00:00:00Z", "2024-05-01T00:00:00Z")).unwrap();
+        assert_eq!((error_code(&frame), frame["id"].clone()), ("bad_request", json!(5)));
+        let frame = answer(&mut session, json!({"id": 6, "method": "getHistory"})).unwrap();
+        assert_eq!((error_code(&frame), frame["id"].clone()), ("bad_request", json!(6)));
+    }
+
+    #[test]
+    fn get_account_info_validates_before_loading() {
+        let mut session = session();
+        let request = |address: &str| json!({"id": [7], "method": "getAccountInfo", "params": {"address": address}});
+        assert!(answer(&mut session, request(&key(1))).is_none());
+
+        let frame = answer(&mut session, request("not-a-key")).unwrap();
+        assert_eq!((error_code(&frame), frame["id"].clone()), ("bad_request", json!([7])));
+    }
+
+    #[test]
+    fn unknown_methods_are_answered_with_their_id() {
+        let frame = answer(&mut session(), json!({"id": 8, "method": "getTrades"})).unwrap();
+        assert_eq!(error_code(&frame), "unknown_method");
+        assert_eq!((frame["version"].clone(), frame["id"].clone()), (json!(1), json!(8)));
+    }
+
+    #[test]
+    fn malformed_requests_are_answered_with_their_id() {
+        let mut session = session();
+        // No method.
+        let frame = answer(&mut session, json!({"id": "x", "params": {}})).unwrap();
+        assert_eq!((error_code(&frame), frame["id"].clone()), ("bad_request", json!("x")));
+        // Wrong protocol version.
+        let frame = answer(&mut session, json!({"version": 2, "id": "y", "method": "subscribe"})).unwrap();
+        assert_eq!((error_code(&frame), frame["id"].clone()), ("bad_request", json!("y")));
+        // Not an object at all: no ID to answer with.
+        let frame = answer(&mut session, json!([1, 2])).unwrap();
+        assert_eq!((error_code(&frame), frame["id"].clone()), ("bad_request", Value::Null));
+
+        let (id, request) = parse_request("{not json");
+        assert_eq!(id, Value::Null);
+        assert!(matches!(request, Err(ApiError::BadRequest(_))));
+    }
+}
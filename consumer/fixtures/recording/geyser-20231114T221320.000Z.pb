@@ -0,0 +1,5 @@
+Ă
+bondingĂ
+bondingĂ
+bondingĂ
+b
\ No newline at end of file
@@ -0,0 +1,185 @@
+//! Authenticated HTTP control plane. Every request needs
+//! `Authorization: Bearer <admin.token>`.
+//!
+//! | Method | Path                   | Effect                                   |
+//! |--------|------------------------|------------------------------------------|
+//! | GET    | `/state`               | last slot, lag, reconnects, sinks        |
+//! | GET    | `/filters`             | active filters                           |
+//! | POST   | `/filters`             | add or replace a filter (`FilterSpec`)   |
+//! | DELETE | `/filters/:name`       | remove a filter                          |
+//! | POST   | `/sinks/:name/pause`   | spool the sink's points instead          |
+//! | POST   | `/sinks/:name/resume`  | write again and replay the spool         |
+//! | POST   | `/backfill`            | backfill `{"from_slot", "to_slot"}`      |
+//!
+//! Filter changes are applied to the running subscription but not written
+//! back to the configuration file.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc};
+use tracing::info;
+
+use crate::backfill::Backfill;
+use crate::config::Secret;
+use crate::filters::{FilterRegistry, FilterSpec};
+use crate::status::{StatusSnapshot, StreamStatus};
+use crate::writer::Sinks;
+
+#[derive(Clone)]
+pub struct AdminState {
+    pub token: Secret,
+    pub registry: Arc<FilterRegistry>,
+    pub sinks: Sinks,
+    pub backfill: Arc<Backfill>,
+    pub status: Arc<StreamStatus>,
+}
+
+pub async fn serve(listen: SocketAddr, state: AdminState) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/state", get(get_state))
+        .route("/filters", get(list_filters).post(upsert_filter))
+        .route("/filters/:name", delete(remove_filter))
+        .route("/sinks/:name/pause", post(pause_sink))
+        .route("/sinks/:name/resume", post(resume_sink))
+        .route("/backfill", post(trigger_backfill))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .with_state(state);
+
+    info!(%listen, "admin endpoint listening");
+    axum::Server::bind(&listen)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
+
+async fn authenticate<B>(
+    State(state): State<AdminState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map_or(false, |token| constant_time_eq(token.as_bytes(), state.token.expose().as_bytes()));
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+type ApiError = (StatusCode, String);
+
+#[derive(Serialize)]
+struct StateResponse {
+    #[serde(flatten)]
+    stream: StatusSnapshot,
+    sinks: Vec<SinkState>,
+}
+
+#[derive(Serialize)]
+struct SinkState {
+    name: String,
+    paused: bool,
+    queue_depth: usize,
+    spooled: usize,
+}
+
+async fn get_state(State(state): State<AdminState>) -> Json<StateResponse> {
+    let sinks = state
+        .sinks
+        .writers()
+        .iter()
+        .map(|writer| SinkState {
+            name: writer.name().to_string(),
+            paused: writer.is_paused(),
+            queue_depth: writer.queue_depth(),
+            spooled: writer.spooled(),
+        })
+        .collect();
+    Json(StateResponse {
+        stream: state.status.snapshot(),
+        sinks,
+    })
+}
+
+async fn list_filters(State(state): State<AdminState>) -> Json<Vec<FilterSpec>> {
+    Json(state.registry.list())
+}
+
+async fn upsert_filter(
+    State(state): State<AdminState>,
+    Json(filter): Json<FilterSpec>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .registry
+        .upsert(filter)
+        .map_err(|error| (StatusCode::BAD_REQUEST, format!("{error:#}")))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_filter(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if !state.registry.remove(&name) {
+        return Err((StatusCode::NOT_FOUND, format!("no filter named {name:?}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pause_sink(state: State<AdminState>, name: Path<String>) -> Result<StatusCode, ApiError> {
+    set_paused(state, name, true)
+}
+
+async fn resume_sink(state: State<AdminState>, name: Path<String>) -> Result<StatusCode, ApiError> {
+    set_paused(state, name, false)
+}
+
+fn set_paused(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    paused: bool,
+) -> Result<StatusCode, ApiError> {
+    let writer = state
+        .sinks
+        .get(&name)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("no sink named {name:?}")))?;
+    writer.set_paused(paused);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct BackfillRequest {
+    from_slot: u64,
+    /// Defaults to the current tip.
+    to_slot: Option<u64>,
+}
+
+async fn trigger_backfill(
+    State(state): State<AdminState>,
+    Json(request): Json<BackfillRequest>,
+) -> Result<StatusCode, ApiError> {
+    if request.from_slot == 0 || request.to_slot.map_or(false, |to| to < request.from_slot) {
+        return Err((StatusCode::BAD_REQUEST, "invalid slot range".to_string()));
+    }
+    crate::spawn_backfill(
+        state.backfill.clone(),
+        request.from_slot - 1,
+        request.to_slot,
+        state.sinks.clone(),
+    );
+    Ok(StatusCode::ACCEPTED)
+}
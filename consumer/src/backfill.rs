@@ -0,0 +1,346 @@
+use anyhow::{anyhow, Context};
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::{RpcBlockConfig, RpcTransactionConfig},
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, message::VersionedMessage, pubkey::Pubkey,
+    signature::Signature,
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, TransactionDetails, UiInstruction,
+    UiTransactionEncoding, UiTransactionStatusMeta, UiTransactionTokenBalance,
+};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    str::FromStr,
+    sync::Arc,
+};
+use tracing::info;
+use yellowstone_grpc_proto::prelude as proto;
+
+use crate::config::Commitment;
+use crate::filters::{DecoderKind, FilterRegistry};
+use crate::metrics;
+use crate::writer::{Receipt, Sinks};
+use crate::Source;
+
+/// `getSignaturesForAddress` page size; 1000 is the RPC maximum.
+const SIGNATURES_PAGE_LIMIT: usize = 1000;
+
+/// Replays transactions matching the current filters that the geyser stream
+/// missed while the consumer was disconnected.
+pub struct Backfill {
+    rpc_client: RpcClient,
+    commitment: CommitmentConfig,
+    registry: Arc<FilterRegistry>,
+}
+
+impl Backfill {
+    /// Fetches at the commitment live points are held back for, but at least
+    /// confirmed: RPC serves neither transactions nor blocks below that.
+    pub fn new(rpc_url: String, commitment: Commitment, registry: Arc<FilterRegistry>) -> Self {
+        let commitment = match commitment {
+            Commitment::Processed | Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        };
+        Self {
+            rpc_client: RpcClient::new_with_commitment(rpc_url, commitment),
+            commitment,
+            registry,
+        }
+    }
+
+    /// Current slot at the backfill commitment, used as the upper bound of a
+    /// gap.
+    pub async fn tip(&self) -> anyhow::Result<u64> {
+        Ok(count_rpc("getSlot", self.rpc_client.get_slot().await)?)
+    }
+
+    /// Fetches every successful transaction of the filtered addresses with
+    /// `after < slot <= until` and pushes it through the regular transaction
+    /// path, oldest first. Returns the number of transactions and the
+    /// receipt of their points.
+    pub async fn run(&self, after: u64, until: u64, writer: &Sinks) -> anyhow::Result<(usize, Receipt)> {
+        // A transaction touching several watched addresses is listed once per
+        // address; keep one copy with the strongest decoder.
+        let mut found: HashMap<Signature, (u64, DecoderKind)> = HashMap::new();
+        for (address, decoder) in self.registry.backfill_addresses() {
+            for (slot, signature) in self.signatures(&address, after, until).await? {
+                match found.entry(signature) {
+                    Entry::Occupied(mut entry) => {
+                        let (_, existing) = entry.get_mut();
+                        *existing = (*existing).max(decoder);
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert((slot, decoder));
+                    }
+                }
+            }
+        }
+        let mut signatures: Vec<(Signature, u64, DecoderKind)> = found
+            .into_iter()
+            .map(|(signature, (slot, decoder))| (signature, slot, decoder))
+            .collect();
+        signatures.sort_by_key(|(_, slot, _)| *slot);
+        info!(
+            transactions = signatures.len(),
+            from_slot = after + 1,
+            to_slot = until,
+            "backfilling"
+        );
+
+        let mut receipt = Receipt::default();
+        let mut block: Option<(u64, HashMap<Signature, u64>)> = None;
+        for (signature, slot, decoder) in &signatures {
+            // Signatures are sorted by slot, so each block is fetched once.
+            if block.as_ref().map_or(true, |(block_slot, _)| block_slot != slot) {
+                block = Some((*slot, self.block_indexes(*slot).await?));
+            }
+            let index = block
+                .as_ref()
+                .and_then(|(_, indexes)| indexes.get(signature).copied())
+                .ok_or_else(|| anyhow!("transaction {signature} is not in block {slot}"))?;
+            let tx = self
+                .rpc_client
+                .get_transaction_with_config(
+                    signature,
+                    RpcTransactionConfig {
+                        encoding: Some(UiTransactionEncoding::Base64),
+                        commitment: Some(self.commitment),
+                        max_supported_transaction_version: Some(0),
+                    },
+                )
+                .await;
+            let tx = count_rpc("getTransaction", tx)
+                .with_context(|| format!("failed to fetch transaction {signature}"))?;
+
+            let slot = tx.slot;
+            let block_time = tx.block_time.unwrap_or_else(|| chrono::Utc::now().timestamp());
+            let info = to_update_transaction_info(signature, index, tx)?;
+            // Fetched at or above the configured commitment, so nothing is held
+            // back.
+            for point in crate::process_transaction(slot, info, block_time, Source::Backfill, *decoder)? {
+                receipt.merge(writer.write(point));
+            }
+        }
+        Ok((signatures.len(), receipt))
+    }
+
+    /// Position of every transaction in the block of `slot`, the `index`
+    /// geyser reports for live transactions.
+    async fn block_indexes(&self, slot: u64) -> anyhow::Result<HashMap<Signature, u64>> {
+        let block = self
+            .rpc_client
+            .get_block_with_config(
+                slot,
+                RpcBlockConfig {
+                    encoding: None,
+                    transaction_details: Some(TransactionDetails::Signatures),
+                    rewards: Some(false),
+                    commitment: Some(self.commitment),
+                    max_supported_transaction_version: Some(0),
+                },
+            )
+            .await;
+        let block = count_rpc("getBlock", block).with_context(|| format!("failed to fetch block {slot}"))?;
+        block
+            .signatures
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .map(|(index, signature)| Ok((Signature::from_str(signature)?, index as u64)))
+            .collect()
+    }
+
+    /// Successful signatures of `address` with `after < slot <= until`,
+    /// oldest first.
+    async fn signatures(
+        &self,
+        address: &Pubkey,
+        after: u64,
+        until: u64,
+    ) -> anyhow::Result<Vec<(u64, Signature)>> {
+        let mut signatures = Vec::new();
+        let mut before = None;
+        loop {
+            let page = self
+                .rpc_client
+                .get_signatures_for_address_with_config(
+                    address,
+                    GetConfirmedSignaturesForAddress2Config {
+                        before,
+                        until: None,
+                        limit: Some(SIGNATURES_PAGE_LIMIT),
+                        commitment: Some(self.commitment),
+                    },
+                )
+                .await;
+            let page = count_rpc("getSignaturesForAddress", page)?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            let reached_gap_start = last.slot <= after;
+            before = Some(Signature::from_str(&last.signature)?);
+
+            for status in page {
+                if status.slot > after && status.slot <= until && status.err.is_none() {
+                    signatures.push((status.slot, Signature::from_str(&status.signature)?));
+                }
+            }
+            if reached_gap_start {
+                break;
+            }
+        }
+
+        // RPC returns newest first.
+        signatures.reverse();
+        Ok(signatures)
+    }
+}
+
+fn count_rpc<T, E>(method: &str, result: Result<T, E>) -> Result<T, E> {
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    metrics::RPC_CALLS.with_label_values(&[method, outcome]).inc();
+    result
+}
+
+/// Rebuilds the geyser representation of an RPC transaction so backfill and
+/// live updates share one decode path.
+fn to_update_transaction_info(
+    signature: &Signature,
+    index: u64,
+    tx: EncodedConfirmedTransactionWithStatusMeta,
+) -> anyhow::Result<proto::SubscribeUpdateTransactionInfo> {
+    let versioned = tx
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| anyhow!("failed to decode transaction {signature}"))?;
+    let meta = tx
+        .transaction
+        .meta
+        .ok_or_else(|| anyhow!("transaction {signature} has no status meta"))?;
+
+    let message = &versioned.message;
+    let header = message.header();
+    let transaction = proto::Transaction {
+        signatures: versioned
+            .signatures
+            .iter()
+            .map(|signature| signature.as_ref().to_vec())
+            .collect(),
+        message: Some(proto::Message {
+            header: Some(proto::MessageHeader {
+                num_required_signatures: header.num_required_signatures.into(),
+                num_readonly_signed_accounts: header.num_readonly_signed_accounts.into(),
+                num_readonly_unsigned_accounts: header.num_readonly_unsigned_accounts.into(),
+            }),
+            account_keys: message
+                .static_account_keys()
+                .iter()
+                .map(|key| key.to_bytes().to_vec())
+                .collect(),
+            recent_blockhash: message.recent_blockhash().to_bytes().to_vec(),
+            instructions: message
+                .instructions()
+                .iter()
+                .map(|ix| proto::CompiledInstruction {
+                    program_id_index: ix.program_id_index.into(),
+                    accounts: ix.accounts.clone(),
+                    data: ix.data.clone(),
+                })
+                .collect(),
+            versioned: matches!(message, VersionedMessage::V0(_)),
+            address_table_lookups: message
+                .address_table_lookups()
+                .unwrap_or_default()
+                .iter()
+                .map(|lookup| proto::MessageAddressTableLookup {
+                    account_key: lookup.account_key.to_bytes().to_vec(),
+                    writable_indexes: lookup.writable_indexes.clone(),
+                    readonly_indexes: lookup.readonly_indexes.clone(),
+                })
+                .collect(),
+        }),
+    };
+
+    Ok(proto::SubscribeUpdateTransactionInfo {
+        signature: signature.as_ref().to_vec(),
+        is_vote: false,
+        transaction: Some(transaction),
+        meta: Some(to_status_meta(meta)?),
+        index,
+    })
+}
+
+fn to_status_meta(meta: UiTransactionStatusMeta) -> anyhow::Result<proto::TransactionStatusMeta> {
+    let inner_instructions = Option::from(meta.inner_instructions)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|inner| {
+            let instructions = inner
+                .instructions
+                .into_iter()
+                .filter_map(|ix| match ix {
+                    UiInstruction::Compiled(ix) => Some(ix),
+                    // Only produced for jsonParsed encoding, which we never request.
+                    UiInstruction::Parsed(_) => None,
+                })
+                .map(|ix| {
+                    Ok(proto::InnerInstruction {
+                        program_id_index: ix.program_id_index.into(),
+                        accounts: ix.accounts,
+                        data: bs58::decode(&ix.data).into_vec()?,
+                        stack_height: ix.stack_height,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?;
+            Ok(proto::InnerInstructions {
+                index: inner.index.into(),
+                instructions,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let loaded_addresses = Option::from(meta.loaded_addresses).unwrap_or_default();
+    let decode_keys = |keys: Vec<String>| {
+        keys.iter()
+            .map(|key| Ok(Pubkey::from_str(key)?.to_bytes().to_vec()))
+            .collect::<anyhow::Result<Vec<_>>>()
+    };
+
+    Ok(proto::TransactionStatusMeta {
+        fee: meta.fee,
+        pre_balances: meta.pre_balances,
+        post_balances: meta.post_balances,
+        inner_instructions,
+        log_messages: Option::from(meta.log_messages).unwrap_or_default(),
+        pre_token_balances: to_token_balances(meta.pre_token_balances.into()),
+        post_token_balances: to_token_balances(meta.post_token_balances.into()),
+        loaded_writable_addresses: decode_keys(loaded_addresses.writable)?,
+        loaded_readonly_addresses: decode_keys(loaded_addresses.readonly)?,
+        compute_units_consumed: meta.compute_units_consumed.into(),
+        ..Default::default()
+    })
+}
+
+fn to_token_balances(balances: Option<Vec<UiTransactionTokenBalance>>) -> Vec<proto::TokenBalance> {
+    balances
+        .unwrap_or_default()
+        .into_iter()
+        .map(|balance| proto::TokenBalance {
+            account_index: balance.account_index.into(),
+            mint: balance.mint,
+            ui_token_amount: Some(proto::UiTokenAmount {
+                ui_amount: balance.ui_token_amount.ui_amount.unwrap_or_default(),
+                decimals: balance.ui_token_amount.decimals.into(),
+                amount: balance.ui_token_amount.amount,
+                ui_amount_string: balance.ui_token_amount.ui_amount_string,
+            }),
+            owner: Option::from(balance.owner).unwrap_or_default(),
+            program_id: Option::from(balance.program_id).unwrap_or_default(),
+        })
+        .collect()
+}
@@ -0,0 +1,44 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential backoff with "equal jitter": every delay is drawn from
+/// `[current / 2, current]`, and `current` doubles up to `max` after each attempt.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            current: min,
+            attempt: 0,
+        }
+    }
+
+    /// Delay to wait before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let half = self.current / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        let delay = half + Duration::from_millis(jitter);
+
+        self.current = (self.current * 2).min(self.max);
+        self.attempt += 1;
+        delay
+    }
+
+    /// Number of delays handed out since the last reset.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.min;
+        self.attempt = 0;
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io::ErrorKind, path::PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// How far the sinks have durably caught up: every point of `slot` and below
+/// was accepted or spooled by every sink.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub slot: u64,
+    /// Progress per filter name, kept for removed filters too so that one
+    /// added back resumes where it stopped.
+    pub filters: BTreeMap<String, FilterCheckpoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilterCheckpoint {
+    /// Every point of the filter up to this slot is sunk.
+    pub slot: u64,
+    /// Transaction of the last sunk update the filter matched, absent for
+    /// account updates.
+    pub signature: Option<String>,
+}
+
+/// Persists the checkpoint as JSON so that a restart knows where its gap
+/// starts.
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    /// `None` for a store that forgets everything, used while replaying.
+    path: Option<PathBuf>,
+}
+
+impl CheckpointStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+        }
+    }
+
+    /// Loads nothing and saves nowhere.
+    pub fn disabled() -> Self {
+        Self { path: None }
+    }
+
+    pub async fn load(&self) -> anyhow::Result<Option<Checkpoint>> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+        let checkpoint = serde_json::from_str(&contents)
+            .with_context(|| format!("invalid checkpoint in {}", path.display()))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Writes and syncs a sibling temp file and renames it over the
+    /// checkpoint, so a crash mid-write never leaves a truncated file behind.
+    pub async fn save(&self, checkpoint: &Checkpoint) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        let contents = serde_json::to_vec(checkpoint)?;
+        let mut file = tokio::fs::File::create(&tmp)
+            .await
+            .with_context(|| format!("failed to create {}", tmp.display()))?;
+        file.write_all(&contents)
+            .await
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        file.sync_all()
+            .await
+            .with_context(|| format!("failed to sync {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, path)
+            .await
+            .with_context(|| format!("failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+//! Holds the points of every slot until the slot reaches the configured
+//! commitment, so forks never reach the sinks.
+//!
+//! The stream is subscribed at `processed` together with slot status updates.
+//! Points are buffered per slot and released once the slot (or a descendant,
+//! which implies its ancestors) reaches the target commitment. When a slot is
+//! finalized, every buffered slot below it that is not one of its ancestors
+//! was skipped or orphaned and is dropped; a released slot in that position
+//! gets an `orphaned_slots` marker so readers can exclude its points.
+
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::warn;
+use yellowstone_grpc_proto::prelude::CommitmentLevel;
+
+/// Buffered slots are released regardless of status beyond this, so a server
+/// that stops sending slot updates cannot grow the buffer without bound.
+const MAX_PENDING_SLOTS: usize = 2048;
+
+/// Finalized slots remembered below the root, so late updates of the chain
+/// are not mistaken for dead forks.
+const MAX_FINALIZED_SLOTS: usize = 2048;
+
+#[derive(Debug)]
+pub struct SlotEvents<T> {
+    /// Updates that reached the target commitment, oldest slot first.
+    pub released: Vec<(u64, Vec<T>)>,
+    /// Slots whose points were dropped before reaching any sink.
+    pub discarded: Vec<u64>,
+    /// Slots whose points were already written and are now known dead.
+    pub dead: Vec<u64>,
+}
+
+impl<T> Default for SlotEvents<T> {
+    fn default() -> Self {
+        Self {
+            released: Vec::new(),
+            discarded: Vec::new(),
+            dead: Vec::new(),
+        }
+    }
+}
+
+/// Buffers updates of type `T` per slot.
+#[derive(Debug)]
+pub struct SlotTracker<T> {
+    target: CommitmentLevel,
+    pending: BTreeMap<u64, Vec<T>>,
+    /// Highest status seen per slot.
+    status: BTreeMap<u64, CommitmentLevel>,
+    parents: BTreeMap<u64, u64>,
+    /// Released before finalization; still able to turn out orphaned.
+    unfinalized: BTreeSet<u64>,
+    /// The most recent slots of the finalized chain, root included.
+    finalized: BTreeSet<u64>,
+    last_finalized: Option<u64>,
+}
+
+impl<T> SlotTracker<T> {
+    pub fn new(target: CommitmentLevel) -> Self {
+        Self {
+            target,
+            pending: BTreeMap::new(),
+            status: BTreeMap::new(),
+            parents: BTreeMap::new(),
+            unfinalized: BTreeSet::new(),
+            finalized: BTreeSet::new(),
+            last_finalized: None,
+        }
+    }
+
+    /// Queues `update` of `slot`, releasing right away when the slot already
+    /// qualifies.
+    pub fn hold(&mut self, slot: u64, update: T) -> SlotEvents<T> {
+        let mut events = SlotEvents::default();
+        if self.last_finalized.map_or(false, |finalized| slot <= finalized) && !self.finalized.contains(&slot) {
+            // Below the root without being part of it: a late update from a
+            // dead fork.
+            events.discarded.push(slot);
+            return events;
+        }
+
+        self.pending.entry(slot).or_default().push(update);
+        if self.target == CommitmentLevel::Processed || self.reached_target(slot) {
+            self.release(slot, &mut events);
+        }
+        self.enforce_capacity(&mut events);
+        events
+    }
+
+    /// Applies a slot status update.
+    pub fn update(&mut self, slot: u64, parent: Option<u64>, status: CommitmentLevel) -> SlotEvents<T> {
+        let mut events = SlotEvents::default();
+        if let Some(parent) = parent {
+            self.parents.insert(slot, parent);
+        }
+        let current = self.status.entry(slot).or_insert(status);
+        if (status as i32) > (*current as i32) {
+            *current = status;
+        }
+
+        if (status as i32) >= (self.target as i32) {
+            // A confirmed or finalized slot implies the same for its ancestors.
+            for ancestor in self.ancestors(slot) {
+                self.release(ancestor, &mut events);
+            }
+        }
+        if status == CommitmentLevel::Finalized {
+            self.finalize(slot, &mut events);
+        }
+        events.released.sort_by_key(|(slot, _)| *slot);
+        events
+    }
+
+    fn reached_target(&self, slot: u64) -> bool {
+        self.finalized.contains(&slot)
+            || self
+                .status
+                .get(&slot)
+                .map_or(false, |status| (*status as i32) >= (self.target as i32))
+    }
+
+    fn release(&mut self, slot: u64, events: &mut SlotEvents<T>) {
+        if let Some(updates) = self.pending.remove(&slot) {
+            if !self.finalized.contains(&slot) && self.status.get(&slot) != Some(&CommitmentLevel::Finalized) {
+                self.unfinalized.insert(slot);
+            }
+            events.released.push((slot, updates));
+        }
+    }
+
+    /// `slot` and every known ancestor, newest first.
+    fn ancestors(&self, slot: u64) -> Vec<u64> {
+        let mut chain = vec![slot];
+        let mut current = slot;
+        while let Some(parent) = self.parents.get(&current) {
+            chain.push(*parent);
+            current = *parent;
+        }
+        chain
+    }
+
+    fn finalize(&mut self, root: u64, events: &mut SlotEvents<T>) {
+        let chain: BTreeSet<u64> = self.ancestors(root).into_iter().collect();
+        for slot in &chain {
+            self.status.insert(*slot, CommitmentLevel::Finalized);
+            self.unfinalized.remove(slot);
+        }
+        self.finalized.extend(&chain);
+        while self.finalized.len() > MAX_FINALIZED_SLOTS {
+            self.finalized.pop_first();
+        }
+
+        // Parents are only known for slots seen while connected; below the
+        // oldest known ancestor nothing can be proven dead, so buffered
+        // points are released and released slots forgotten.
+        let known_from = chain.first().copied().unwrap_or(root);
+        let below: Vec<u64> = self.pending.range(..root).map(|(slot, _)| *slot).collect();
+        for slot in below {
+            if slot < known_from {
+                self.release(slot, events);
+            } else if !chain.contains(&slot) {
+                self.pending.remove(&slot);
+                events.discarded.push(slot);
+            }
+        }
+        let below: Vec<u64> = self.unfinalized.range(..root).copied().collect();
+        for slot in below {
+            self.unfinalized.remove(&slot);
+            if slot >= known_from {
+                events.dead.push(slot);
+            }
+        }
+
+        self.last_finalized = Some(self.last_finalized.map_or(root, |last| last.max(root)));
+        // Nothing below the root can change any more; `finalized` keeps which
+        // of those slots are on the chain.
+        self.status = self.status.split_off(&root);
+        self.parents = self.parents.split_off(&root);
+        self.unfinalized.retain(|slot| *slot > root);
+    }
+
+    fn enforce_capacity(&mut self, events: &mut SlotEvents<T>) {
+        while self.pending.len() > MAX_PENDING_SLOTS {
+            let Some((slot, _)) = self.pending.first_key_value() else {
+                break;
+            };
+            let slot = *slot;
+            warn!(slot, "no commitment update for slot, releasing it unconfirmed");
+            self.release(slot, events);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CommitmentLevel::{Confirmed, Finalized, Processed};
+
+    fn released(events: &SlotEvents<&'static str>) -> Vec<(u64, Vec<&'static str>)> {
+        events.released.clone()
+    }
+
+    #[test]
+    fn processed_releases_immediately() {
+        let mut tracker = SlotTracker::new(Processed);
+        let events = tracker.hold(10, "a");
+        assert_eq!(released(&events), vec![(10, vec!["a"])]);
+    }
+
+    #[test]
+    fn holds_until_confirmed() {
+        let mut tracker = SlotTracker::new(Confirmed);
+        assert!(tracker.hold(10, "a").released.is_empty());
+        assert!(tracker.hold(10, "b").released.is_empty());
+        assert!(tracker.update(10, Some(9), Processed).released.is_empty());
+        let events = tracker.update(10, Some(9), Confirmed);
+        assert_eq!(released(&events), vec![(10, vec!["a", "b"])]);
+        // Later updates of a confirmed slot go straight through.
+        assert_eq!(released(&tracker.hold(10, "c")), vec![(10, vec!["c"])]);
+    }
+
+    #[test]
+    fn confirming_a_descendant_releases_its_ancestors() {
+        let mut tracker = SlotTracker::new(Confirmed);
+        tracker.hold(10, "a");
+        tracker.hold(11, "b");
+        tracker.update(11, Some(10), Processed);
+        tracker.update(12, Some(11), Processed);
+        let events = tracker.update(12, Some(11), Confirmed);
+        assert_eq!(released(&events), vec![(10, vec!["a"]), (11, vec!["b"])]);
+    }
+
+    #[test]
+    fn finalizing_discards_held_slots_of_other_forks() {
+        let mut tracker = SlotTracker::new(Confirmed);
+        tracker.update(10, Some(9), Processed);
+        tracker.update(11, Some(10), Processed);
+        // 12 forks off 10 and dies.
+        tracker.update(12, Some(10), Processed);
+        tracker.hold(11, "kept");
+        tracker.hold(12, "forked");
+        tracker.update(13, Some(11), Processed);
+        let events = tracker.update(13, Some(11), Finalized);
+        assert_eq!(released(&events), vec![(11, vec!["kept"])]);
+        assert_eq!(events.discarded, vec![12]);
+        assert!(events.dead.is_empty());
+        // A late update of the dead fork is dropped as well.
+        assert_eq!(tracker.hold(12, "late").discarded, vec![12]);
+    }
+
+    #[test]
+    fn finalizing_reports_released_slots_of_other_forks_as_dead() {
+        let mut tracker = SlotTracker::new(Processed);
+        tracker.update(10, Some(9), Processed);
+        tracker.update(11, Some(10), Processed);
+        tracker.update(12, Some(10), Processed);
+        assert_eq!(released(&tracker.hold(11, "kept")), vec![(11, vec!["kept"])]);
+        assert_eq!(released(&tracker.hold(12, "orphaned")), vec![(12, vec!["orphaned"])]);
+        tracker.update(13, Some(11), Processed);
+        let events = tracker.update(13, Some(11), Finalized);
+        assert_eq!(events.dead, vec![12]);
+        assert!(events.discarded.is_empty());
+    }
+
+    #[test]
+    fn slots_below_the_known_chain_are_released_on_finalization() {
+        // Held before any parent information, e.g. right after connecting.
+        let mut tracker = SlotTracker::new(Confirmed);
+        tracker.hold(5, "early");
+        tracker.update(11, Some(10), Processed);
+        let events = tracker.update(11, Some(10), Finalized);
+        assert_eq!(released(&events), vec![(5, vec!["early"])]);
+        assert!(events.discarded.is_empty());
+    }
+
+    #[test]
+    fn capacity_releases_the_oldest_slots() {
+        let mut tracker = SlotTracker::new(Confirmed);
+        for slot in 0..MAX_PENDING_SLOTS as u64 {
+            assert!(tracker.hold(slot, "held").released.is_empty());
+        }
+        let events = tracker.hold(MAX_PENDING_SLOTS as u64, "held");
+        assert_eq!(released(&events), vec![(0, vec!["held"])]);
+    }
+
+    #[test]
+    fn late_updates_of_finalized_ancestors_are_released() {
+        let mut tracker = SlotTracker::new(Confirmed);
+        tracker.update(10, Some(9), Processed);
+        tracker.update(11, Some(10), Processed);
+        // 12 forks off 10 and dies.
+        tracker.update(12, Some(10), Processed);
+        tracker.update(13, Some(11), Processed);
+        tracker.update(13, Some(11), Finalized);
+
+        // Arriving after the root moved past it, e.g. a delayed account write.
+        let events = tracker.hold(10, "late");
+        assert_eq!(released(&events), vec![(10, vec!["late"])]);
+        assert!(events.discarded.is_empty());
+        assert_eq!(released(&tracker.hold(11, "late")), vec![(11, vec!["late"])]);
+        assert_eq!(tracker.hold(12, "late").discarded, vec![12]);
+
+        // Nor is it reported dead by the next finalization.
+        tracker.update(14, Some(13), Processed);
+        let events = tracker.update(14, Some(13), Finalized);
+        assert!(events.dead.is_empty());
+    }
+}
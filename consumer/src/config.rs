@@ -0,0 +1,730 @@
+//! Consumer configuration: a TOML file (see `consumer.example.toml`) with
+//! command-line flags on top. Every flag can also be set from the environment
+//! variable named in its help; flags win over the file.
+
+use anyhow::{anyhow, bail, Context};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Deserializer};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::HashSet,
+    fmt,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use yellowstone_grpc_proto::prelude::CommitmentLevel;
+
+use crate::filters::{DecoderKind, FilterKind, FilterSpec};
+use crate::logging::{secret_fragments, LogConfig, LogFormat};
+use crate::recording::{RecordConfig, ReplayConfig};
+use crate::sink::{AmqpConfig, InfluxConfig, JsonConfig, PostgresConfig, SinkConfig};
+use crate::writer::WriterConfig;
+
+/// spl-token-bonding on mainnet.
+const DEFAULT_PROGRAM_ID: &str = "TBondmkCYxaPCKG4CHYfVTcwQ8on31xnJrPzk8F8WsS";
+
+#[derive(Debug, Parser)]
+#[command(about = "Streams spl-token-bonding activity from geyser into the configured sinks")]
+pub struct Cli {
+    /// TOML configuration file.
+    #[arg(short, long, env = "CONSUMER_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Yellowstone gRPC endpoint.
+    #[arg(long, env = "GEYSER_ENDPOINT")]
+    pub geyser_endpoint: Option<String>,
+    /// File holding the geyser x-token.
+    #[arg(long, env = "X_TOKEN_FILE")]
+    pub x_token_file: Option<PathBuf>,
+    /// Solana JSON-RPC endpoint used for backfills.
+    #[arg(long, env = "RPC_URL")]
+    pub rpc_url: Option<String>,
+    /// Program to watch; repeat for several.
+    #[arg(long = "program-id", env = "PROGRAM_IDS", value_delimiter = ',')]
+    pub program_ids: Vec<String>,
+    #[arg(long, env = "COMMITMENT", value_enum)]
+    pub commitment: Option<Commitment>,
+    #[arg(long, env = "CHECKPOINT_PATH")]
+    pub checkpoint_path: Option<PathBuf>,
+    #[arg(long, env = "SPOOL_DIR")]
+    pub spool_dir: Option<PathBuf>,
+    /// Address of the admin HTTP endpoint, e.g. 127.0.0.1:9100.
+    #[arg(long, env = "ADMIN_LISTEN")]
+    pub admin_listen: Option<SocketAddr>,
+    /// Address of the Prometheus `/metrics` endpoint, e.g. 0.0.0.0:9187.
+    #[arg(long, env = "METRICS_LISTEN")]
+    pub metrics_listen: Option<SocketAddr>,
+    /// Log filter, e.g. `info` or `info,consumer=debug`.
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// Record every geyser message to this directory.
+    #[arg(long, env = "RECORD_DIR")]
+    pub record_dir: Option<PathBuf>,
+    /// Replay a recording (directory or file) instead of connecting to geyser.
+    #[arg(long, env = "REPLAY_PATH")]
+    pub replay: Option<PathBuf>,
+    /// Replay speed relative to the recording; 0 replays as fast as possible.
+    #[arg(long, env = "REPLAY_SPEED", default_value_t = 1.0)]
+    pub replay_speed: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl From<Commitment> for CommitmentLevel {
+    fn from(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Processed => CommitmentLevel::Processed,
+            Commitment::Confirmed => CommitmentLevel::Confirmed,
+            Commitment::Finalized => CommitmentLevel::Finalized,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub geyser: GeyserConfig,
+    #[serde(default)]
+    pub rpc: RpcConfig,
+    /// Shorthand for one program filter named `geyser.filter_name`.
+    #[serde(default)]
+    pub program_ids: Vec<String>,
+    /// `[[filters]]` tables; see [`FilterSpec`].
+    #[serde(default)]
+    pub filters: Vec<FilterSpec>,
+    #[serde(default = "default_checkpoint_path")]
+    pub checkpoint_path: PathBuf,
+    #[serde(default)]
+    pub writer: WriterConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub record: RecordConfig,
+    /// Command line only.
+    #[serde(skip)]
+    pub replay: Option<ReplayConfig>,
+    #[serde(default = "default_sinks")]
+    pub sinks: Vec<SinkSection>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeyserConfig {
+    #[serde(default)]
+    pub endpoint: String,
+    pub x_token: Option<Secret>,
+    /// Points are held back until their slot reaches this commitment.
+    #[serde(default = "default_commitment")]
+    pub commitment: Commitment,
+    /// Name of the filter built from `program_ids`.
+    #[serde(default = "default_filter_name")]
+    pub filter_name: String,
+    #[serde(
+        rename = "connect_timeout_secs",
+        default = "default_connect_timeout",
+        deserialize_with = "seconds"
+    )]
+    pub connect_timeout: Duration,
+    #[serde(
+        rename = "ping_interval_secs",
+        default = "default_ping_interval",
+        deserialize_with = "seconds"
+    )]
+    pub ping_interval: Duration,
+    /// Reconnect after this long without any message from the server.
+    #[serde(
+        rename = "stall_timeout_secs",
+        default = "default_stall_timeout",
+        deserialize_with = "seconds"
+    )]
+    pub stall_timeout: Duration,
+}
+
+impl Default for GeyserConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            x_token: None,
+            commitment: default_commitment(),
+            filter_name: default_filter_name(),
+            connect_timeout: default_connect_timeout(),
+            ping_interval: default_ping_interval(),
+            stall_timeout: default_stall_timeout(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpcConfig {
+    /// May embed a provider API key, hence a secret.
+    pub url: Option<Secret>,
+}
+
+/// The admin endpoint is off unless `listen` is set.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    pub listen: Option<SocketAddr>,
+    /// Bearer token required on every admin request.
+    pub token: Option<Secret>,
+}
+
+/// The metrics endpoint is off unless `listen` is set. It is unauthenticated,
+/// so bind it where only the scraper can reach it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub listen: Option<SocketAddr>,
+}
+
+/// One `[[sinks]]` table, selected by its `type`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SinkSection {
+    Influx {
+        url: String,
+        org: Option<String>,
+        bucket: String,
+        token: Option<Secret>,
+    },
+    Postgres {
+        url: Secret,
+        #[serde(default = "default_postgres_table")]
+        table: String,
+        #[serde(default)]
+        timescale: bool,
+    },
+    Amqp {
+        url: Secret,
+        #[serde(default = "default_amqp_exchange")]
+        exchange: String,
+        #[serde(default = "AmqpConfig::default_measurements")]
+        measurements: Vec<String>,
+    },
+    Json {
+        #[serde(default = "default_json_path")]
+        path: PathBuf,
+    },
+}
+
+impl SinkSection {
+    fn kind(&self) -> &'static str {
+        match self {
+            SinkSection::Influx { .. } => "influx",
+            SinkSection::Postgres { .. } => "postgres",
+            SinkSection::Amqp { .. } => "amqp",
+            SinkSection::Json { .. } => "json",
+        }
+    }
+
+    pub fn to_sink_config(&self) -> SinkConfig {
+        match self {
+            SinkSection::Influx { url, org, bucket, token } => SinkConfig::Influx(InfluxConfig {
+                url: url.clone(),
+                org: org.clone(),
+                bucket: bucket.clone(),
+                token: token.as_ref().map(|token| token.expose().to_string()),
+            }),
+            SinkSection::Postgres { url, table, timescale } => SinkConfig::Postgres(PostgresConfig {
+                url: url.expose().to_string(),
+                table: table.clone(),
+                timescale: *timescale,
+            }),
+            SinkSection::Amqp { url, exchange, measurements } => SinkConfig::Amqp(AmqpConfig {
+                url: url.expose().to_string(),
+                exchange: exchange.clone(),
+                measurements: measurements.clone(),
+            }),
+            SinkSection::Json { path } => SinkConfig::Json(JsonConfig { path: path.clone() }),
+        }
+    }
+}
+
+/// A credential given inline, read from a file or from an environment
+/// variable:
+///
+/// ```toml
+/// x_token = "inline"
+/// x_token = { file = "/run/secrets/x_token" }
+/// x_token = { env = "X_TOKEN" }
+/// ```
+///
+/// Resolved while the configuration is parsed; never printed.
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let value = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read secret from {}", path.display()))?;
+        Ok(Self(value.trim().to_string()))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Source {
+            Inline(String),
+            File { file: PathBuf },
+            Env { env: String },
+        }
+
+        let resolved = match Source::deserialize(deserializer)? {
+            Source::Inline(value) => Ok(Secret(value)),
+            Source::File { file } => Secret::from_file(&file),
+            Source::Env { env } => std::env::var(&env)
+                .map(Secret)
+                .map_err(|_| anyhow!("environment variable {env} is not set")),
+        };
+        resolved.map_err(|error| serde::de::Error::custom(format!("{error:#}")))
+    }
+}
+
+impl Config {
+    /// Parses the command line, reads the file it points to, applies the
+    /// overrides and validates the result.
+    pub fn load() -> anyhow::Result<Self> {
+        let cli = Cli::parse();
+        let mut config: Config = match &cli.config {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                toml::from_str(&contents).with_context(|| format!("invalid {}", path.display()))?
+            }
+            None => toml::from_str("")?,
+        };
+        config.apply(cli)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&mut self, cli: Cli) -> anyhow::Result<()> {
+        if let Some(endpoint) = cli.geyser_endpoint {
+            self.geyser.endpoint = endpoint;
+        }
+        if let Some(path) = cli.x_token_file {
+            self.geyser.x_token = Some(Secret::from_file(&path)?);
+        }
+        if let Some(url) = cli.rpc_url {
+            self.rpc.url = Some(Secret(url));
+        }
+        if !cli.program_ids.is_empty() {
+            self.program_ids = cli.program_ids;
+        }
+        if let Some(commitment) = cli.commitment {
+            self.geyser.commitment = commitment;
+        }
+        if let Some(path) = cli.checkpoint_path {
+            self.checkpoint_path = path;
+        }
+        if let Some(dir) = cli.spool_dir {
+            self.writer.spool_dir = dir;
+        }
+        if let Some(listen) = cli.admin_listen {
+            self.admin.listen = Some(listen);
+        }
+        if let Some(listen) = cli.metrics_listen {
+            self.metrics.listen = Some(listen);
+        }
+        if let Some(level) = cli.log_level {
+            self.log.level = level;
+        }
+        if let Some(format) = cli.log_format {
+            self.log.format = format;
+        }
+        if let Some(dir) = cli.record_dir {
+            self.record.dir = Some(dir);
+        }
+        if let Some(path) = cli.replay {
+            self.replay = Some(ReplayConfig {
+                path,
+                speed: cli.replay_speed,
+            });
+        }
+        Ok(())
+    }
+
+    /// Collects every problem instead of stopping at the first one.
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        if self.replay.is_some() {
+            // No connection is made.
+        } else if self.geyser.endpoint.is_empty() {
+            errors.push("geyser.endpoint is required (or --geyser-endpoint / GEYSER_ENDPOINT)".to_string());
+        } else if let Err(error) = reqwest::Url::parse(&self.geyser.endpoint) {
+            errors.push(format!("geyser.endpoint {:?} is not a URL: {error}", self.geyser.endpoint));
+        }
+        if self.geyser.ping_interval.is_zero() {
+            errors.push("geyser.ping_interval_secs must be positive".to_string());
+        }
+        if self.geyser.stall_timeout <= self.geyser.ping_interval {
+            errors.push("geyser.stall_timeout_secs must be longer than geyser.ping_interval_secs".to_string());
+        }
+        if self.geyser.filter_name.is_empty() {
+            errors.push("geyser.filter_name must not be empty".to_string());
+        }
+        match &self.rpc.url {
+            // A replay resumes from no checkpoint, so it never backfills.
+            None if self.replay.is_some() => {}
+            None => errors.push("rpc.url is required (or --rpc-url / RPC_URL)".to_string()),
+            Some(url) if reqwest::Url::parse(url.expose()).is_err() => {
+                errors.push("rpc.url is not a URL".to_string())
+            }
+            Some(_) => {}
+        }
+
+        let mut valid_program_ids = true;
+        for program_id in &self.program_ids {
+            if Pubkey::from_str(program_id).is_err() {
+                errors.push(format!("program id {program_id:?} is not a valid pubkey"));
+                valid_program_ids = false;
+            }
+        }
+        if valid_program_ids {
+            let mut names = HashSet::new();
+            for filter in self.filters() {
+                if let Err(error) = filter.validate() {
+                    errors.push(format!("{error:#}"));
+                }
+                if !names.insert(filter.name.clone()) {
+                    errors.push(format!("filter name {:?} is used more than once", filter.name));
+                }
+            }
+        }
+
+        if self.writer.batch_size == 0 {
+            errors.push("writer.batch_size must be positive".to_string());
+        }
+        if self.writer.channel_capacity < self.writer.batch_size {
+            errors.push("writer.channel_capacity must be at least writer.batch_size".to_string());
+        }
+        if self.writer.max_attempts == 0 {
+            errors.push("writer.max_attempts must be positive".to_string());
+        }
+        if self.writer.flush_interval.is_zero() {
+            errors.push("writer.flush_interval_ms must be positive".to_string());
+        }
+        if self.writer.drain_timeout.is_zero() {
+            errors.push("writer.drain_timeout_ms must be positive".to_string());
+        }
+
+        if self.admin.listen.is_some()
+            && self.admin.token.as_ref().map_or(true, |token| token.expose().is_empty())
+        {
+            errors.push("admin.token is required when admin.listen is set".to_string());
+        }
+        if tracing_subscriber::EnvFilter::try_new(&self.log.level).is_err() {
+            errors.push(format!("log.level {:?} is not a valid filter", self.log.level));
+        }
+        if self.metrics.listen.is_some() && self.metrics.listen == self.admin.listen {
+            errors.push("metrics.listen and admin.listen must differ".to_string());
+        }
+
+        if let Some(replay) = &self.replay {
+            if !(replay.speed.is_finite() && replay.speed >= 0.0) {
+                errors.push("--replay-speed must be zero or positive".to_string());
+            }
+            if self.record.dir.is_some() {
+                errors.push("recording while replaying is not supported".to_string());
+            }
+        }
+        if self.record.rotate_mb == 0 {
+            errors.push("record.rotate_mb must be positive".to_string());
+        }
+
+        if self.sinks.is_empty() {
+            errors.push("at least one [[sinks]] entry is required".to_string());
+        }
+        // Spool files are named after the sink type.
+        let mut kinds = HashSet::new();
+        for sink in &self.sinks {
+            if !kinds.insert(sink.kind()) {
+                errors.push(format!("sink type {:?} is configured more than once", sink.kind()));
+            }
+            match sink {
+                SinkSection::Influx { url, .. } if reqwest::Url::parse(url).is_err() => {
+                    errors.push(format!("influx sink url {url:?} is not a URL"));
+                }
+                // Interpolated into the DDL and inserts, and into index names.
+                SinkSection::Postgres { table, .. } if !is_sql_identifier(table) => {
+                    errors.push(format!(
+                        "postgres sink table {table:?} must match [A-Za-z_][A-Za-z0-9_]*"
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+        bail!("invalid configuration:\n  - {}", errors.join("\n  - "))
+    }
+
+    /// `[[filters]]` plus the `program_ids` shorthand; the bonding program
+    /// when neither is configured.
+    pub fn filters(&self) -> Vec<FilterSpec> {
+        let mut filters = self.filters.clone();
+        let program_ids = match (&self.program_ids[..], filters.is_empty()) {
+            ([], true) => vec![DEFAULT_PROGRAM_ID.to_string()],
+            (program_ids, _) => program_ids.to_vec(),
+        };
+        if !program_ids.is_empty() {
+            filters.push(FilterSpec {
+                name: self.geyser.filter_name.clone(),
+                kind: FilterKind::Program,
+                pubkeys: program_ids
+                    .iter()
+                    .map(|program_id| Pubkey::from_str(program_id).expect("validated"))
+                    .collect(),
+                decoder: DecoderKind::Bonding,
+            });
+        }
+        filters
+    }
+
+    /// Empty only when replaying without one, in which case backfills
+    /// requested over the admin endpoint fail.
+    pub fn rpc_url(&self) -> &str {
+        self.rpc.url.as_ref().map_or("", |url| url.expose())
+    }
+
+    /// Every secret value, for scrubbing from logs.
+    pub fn secrets(&self) -> Vec<String> {
+        let mut secrets: Vec<&Secret> = [&self.geyser.x_token, &self.rpc.url, &self.admin.token]
+            .into_iter()
+            .flatten()
+            .collect();
+        for sink in &self.sinks {
+            match sink {
+                SinkSection::Influx { token, .. } => secrets.extend(token),
+                SinkSection::Postgres { url, .. } | SinkSection::Amqp { url, .. } => secrets.push(url),
+                SinkSection::Json { .. } => {}
+            }
+        }
+        secrets
+            .into_iter()
+            .flat_map(|secret| secret_fragments(secret.expose()))
+            .collect()
+    }
+}
+
+fn default_checkpoint_path() -> PathBuf {
+    "checkpoint".into()
+}
+
+fn default_commitment() -> Commitment {
+    Commitment::Confirmed
+}
+
+fn default_filter_name() -> String {
+    "bonding".to_string()
+}
+
+fn default_connect_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_ping_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_stall_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_sinks() -> Vec<SinkSection> {
+    vec![SinkSection::Json {
+        path: default_json_path(),
+    }]
+}
+
+fn default_postgres_table() -> String {
+    "bonding_points".to_string()
+}
+
+fn default_amqp_exchange() -> String {
+    "bonding".to_string()
+}
+
+fn default_json_path() -> PathBuf {
+    "-".into()
+}
+
+/// An unquoted SQL identifier that needs no escaping.
+fn is_sql_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().map_or(false, |first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+pub fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"
+        [geyser]
+        endpoint = "http://localhost:10000"
+
+        [rpc]
+        url = "http://localhost:8899"
+    "#;
+
+    /// `extra` goes first so that it may set top-level keys.
+    fn parse(extra: &str) -> Config {
+        toml::from_str(&format!("{extra}\n{BASE}")).unwrap()
+    }
+
+    fn errors(config: &Config) -> Vec<String> {
+        match config.validate() {
+            Ok(()) => Vec::new(),
+            Err(error) => error
+                .to_string()
+                .lines()
+                .skip(1)
+                .map(|line| line.trim_start_matches("  - ").to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn validation_reports_every_problem_at_once() {
+        let cases: &[(&str, &[&str])] = &[
+            ("", &[]),
+            (
+                "[writer]\nbatch_size = 0\nmax_attempts = 0\nflush_interval_ms = 0",
+                &[
+                    "writer.batch_size must be positive",
+                    "writer.max_attempts must be positive",
+                    "writer.flush_interval_ms must be positive",
+                ],
+            ),
+            (
+                "program_ids = [\"not-a-key\"]\n[[sinks]]\ntype = \"json\"\n[[sinks]]\ntype = \"json\"",
+                &[
+                    "program id \"not-a-key\" is not a valid pubkey",
+                    "sink type \"json\" is configured more than once",
+                ],
+            ),
+            (
+                "[admin]\nlisten = \"127.0.0.1:9100\"\n[metrics]\nlisten = \"127.0.0.1:9100\"",
+                &[
+                    "admin.token is required when admin.listen is set",
+                    "metrics.listen and admin.listen must differ",
+                ],
+            ),
+            ("sinks = []", &["at least one [[sinks]] entry is required"]),
+        ];
+        for (extra, expected) in cases {
+            assert_eq!(errors(&parse(extra)), *expected, "{extra}");
+        }
+    }
+
+    #[test]
+    fn required_endpoints_are_reported_together() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(
+            errors(&config),
+            [
+                "geyser.endpoint is required (or --geyser-endpoint / GEYSER_ENDPOINT)",
+                "rpc.url is required (or --rpc-url / RPC_URL)",
+            ]
+        );
+    }
+
+    #[test]
+    fn environment_beats_the_file_and_flags_beat_the_environment() {
+        let file = "[geyser]\nendpoint = \"http://file:10000\"\ncommitment = \"processed\"";
+
+        // The only test that touches these variables.
+        std::env::set_var("GEYSER_ENDPOINT", "http://env:10000");
+        std::env::set_var("COMMITMENT", "finalized");
+        let from_env = Cli::try_parse_from(["consumer"]);
+        let from_flags = Cli::try_parse_from(["consumer", "--geyser-endpoint", "http://flag:10000"]);
+        std::env::remove_var("GEYSER_ENDPOINT");
+        std::env::remove_var("COMMITMENT");
+
+        let mut config: Config = toml::from_str(file).unwrap();
+        config.apply(from_env.unwrap()).unwrap();
+        assert_eq!(config.geyser.endpoint, "http://env:10000");
+        assert_eq!(config.geyser.commitment, Commitment::Finalized);
+
+        let mut config: Config = toml::from_str(file).unwrap();
+        config.apply(from_flags.unwrap()).unwrap();
+        assert_eq!(config.geyser.endpoint, "http://flag:10000");
+
+        // Nothing set keeps the file.
+        let mut config: Config = toml::from_str(file).unwrap();
+        config.apply(Cli::try_parse_from(["consumer"]).unwrap()).unwrap();
+        assert_eq!(config.geyser.endpoint, "http://file:10000");
+        assert_eq!(config.geyser.commitment, Commitment::Processed);
+    }
+
+    #[test]
+    fn sinks_default_to_json_on_stdout_unless_configured() {
+        let config = parse("");
+        assert!(matches!(&config.sinks[..], [SinkSection::Json { path }] if path.as_os_str() == "-"));
+
+        let config = parse("[[sinks]]\ntype = \"postgres\"\nurl = \"host=db\"");
+        match &config.sinks[..] {
+            [SinkSection::Postgres { table, timescale, .. }] => {
+                assert_eq!(table, "bonding_points");
+                assert!(!timescale);
+            }
+            sinks => panic!("parsed {sinks:?}"),
+        }
+    }
+
+    #[test]
+    fn postgres_tables_must_be_plain_identifiers() {
+        for (table, valid) in [
+            ("bonding_points", true),
+            ("_points2", true),
+            ("Points", true),
+            ("2points", false),
+            ("", false),
+            ("public.points", false),
+            ("points; DROP TABLE points", false),
+            ("\"points\"", false),
+        ] {
+            let config = parse(&format!("[[sinks]]\ntype = \"postgres\"\nurl = \"host=db\"\ntable = {table:?}"));
+            assert_eq!(config.validate().is_ok(), valid, "{table:?}");
+        }
+    }
+}
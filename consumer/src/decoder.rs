@@ -0,0 +1,604 @@
+use anchor_lang::{solana_program::hash::hash, AnchorDeserialize};
+use anyhow::anyhow;
+use solana_sdk::pubkey::Pubkey;
+use std::sync::OnceLock;
+use tracing::warn;
+use yellowstone_grpc_proto::prelude::{SubscribeUpdateTransactionInfo, TransactionStatusMeta};
+
+use crate::metrics;
+
+/// Borsh layouts of the spl-token-bonding instruction arguments, mirrored from
+/// the program IDL. Only the instructions we surface amounts for are decoded.
+mod args {
+    use anchor_lang::AnchorDeserialize;
+    use solana_sdk::pubkey::Pubkey;
+
+    #[derive(AnchorDeserialize)]
+    pub struct BuyWithBaseV0Args {
+        pub base_amount: u64,
+        pub minimum_target_amount: u64,
+    }
+
+    #[derive(AnchorDeserialize)]
+    pub struct BuyTargetAmountV0Args {
+        pub target_amount: u64,
+        pub maximum_price: u64,
+    }
+
+    #[derive(AnchorDeserialize)]
+    pub struct BuyV0Args {
+        pub buy_with_base: Option<BuyWithBaseV0Args>,
+        pub buy_target_amount: Option<BuyTargetAmountV0Args>,
+    }
+
+    #[derive(AnchorDeserialize)]
+    pub struct SellV0Args {
+        pub target_amount: u64,
+        pub minimum_price: u64,
+    }
+
+    #[derive(AnchorDeserialize)]
+    pub struct TransferReservesV0Args {
+        pub amount: u64,
+    }
+
+    #[derive(AnchorDeserialize)]
+    pub struct UpdateReserveAuthorityV0Args {
+        pub new_reserve_authority: Option<Pubkey>,
+    }
+
+    #[derive(AnchorDeserialize)]
+    pub struct UpdateCurveV0Args {
+        pub curve_authority: Option<Pubkey>,
+    }
+
+    #[derive(AnchorDeserialize)]
+    pub struct BuyWrappedSolV0Args {
+        pub amount: u64,
+    }
+
+    #[derive(AnchorDeserialize)]
+    pub struct SellWrappedSolV0Args {
+        pub amount: u64,
+        pub all: bool,
+    }
+}
+
+/// How a buy was sized by the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuyAmount {
+    /// Spend exactly `base_amount`, receiving at least `minimum_target_amount`.
+    WithBase {
+        base_amount: u64,
+        minimum_target_amount: u64,
+    },
+    /// Receive exactly `target_amount`, paying at most `maximum_price`.
+    TargetAmount { target_amount: u64, maximum_price: u64 },
+}
+
+/// One spl-token-bonding instruction, with its accounts resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BondingEvent {
+    Buy {
+        token_bonding: Pubkey,
+        base_mint: Pubkey,
+        target_mint: Pubkey,
+        base_storage: Pubkey,
+        base_royalties: Pubkey,
+        target_royalties: Pubkey,
+        /// Target token account receiving the purchase.
+        destination: Pubkey,
+        trader: Pubkey,
+        native: bool,
+        amount: BuyAmount,
+    },
+    Sell {
+        token_bonding: Pubkey,
+        base_mint: Pubkey,
+        target_mint: Pubkey,
+        base_storage: Pubkey,
+        base_royalties: Pubkey,
+        target_royalties: Pubkey,
+        /// Target token account the sold tokens are burned from.
+        source: Pubkey,
+        trader: Pubkey,
+        native: bool,
+        target_amount: u64,
+        minimum_price: u64,
+    },
+    InitializeTokenBonding {
+        token_bonding: Pubkey,
+        base_mint: Pubkey,
+        target_mint: Pubkey,
+        curve: Pubkey,
+        payer: Pubkey,
+    },
+    UpdateTokenBonding {
+        token_bonding: Pubkey,
+        general_authority: Pubkey,
+    },
+    CloseTokenBonding {
+        token_bonding: Pubkey,
+        refund: Pubkey,
+    },
+    UpdateReserveAuthority {
+        token_bonding: Pubkey,
+        new_reserve_authority: Option<Pubkey>,
+    },
+    UpdateCurve {
+        token_bonding: Pubkey,
+        curve: Pubkey,
+        curve_authority: Option<Pubkey>,
+    },
+    TransferReserves {
+        token_bonding: Pubkey,
+        destination: Pubkey,
+        native: bool,
+        amount: u64,
+    },
+    CreateCurve {
+        curve: Pubkey,
+    },
+    InitializeSolStorage,
+    BuyWrappedSol {
+        amount: u64,
+    },
+    SellWrappedSol {
+        amount: u64,
+        all: bool,
+    },
+}
+
+impl BondingEvent {
+    /// Stable name used as the `event_type` tag.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            BondingEvent::Buy { .. } => "buy",
+            BondingEvent::Sell { .. } => "sell",
+            BondingEvent::InitializeTokenBonding { .. } => "initialize_token_bonding",
+            BondingEvent::UpdateTokenBonding { .. } => "update_token_bonding",
+            BondingEvent::CloseTokenBonding { .. } => "close_token_bonding",
+            BondingEvent::UpdateReserveAuthority { .. } => "update_reserve_authority",
+            BondingEvent::UpdateCurve { .. } => "update_curve",
+            BondingEvent::TransferReserves { .. } => "transfer_reserves",
+            BondingEvent::CreateCurve { .. } => "create_curve",
+            BondingEvent::InitializeSolStorage => "initialize_sol_storage",
+            BondingEvent::BuyWrappedSol { .. } => "buy_wrapped_sol",
+            BondingEvent::SellWrappedSol { .. } => "sell_wrapped_sol",
+        }
+    }
+
+    /// The bonding account the instruction acts on, if it has one.
+    pub fn token_bonding(&self) -> Option<&Pubkey> {
+        match self {
+            BondingEvent::Buy { token_bonding, .. }
+            | BondingEvent::Sell { token_bonding, .. }
+            | BondingEvent::InitializeTokenBonding { token_bonding, .. }
+            | BondingEvent::UpdateTokenBonding { token_bonding, .. }
+            | BondingEvent::CloseTokenBonding { token_bonding, .. }
+            | BondingEvent::UpdateReserveAuthority { token_bonding, .. }
+            | BondingEvent::UpdateCurve { token_bonding, .. }
+            | BondingEvent::TransferReserves { token_bonding, .. } => Some(token_bonding),
+            BondingEvent::CreateCurve { .. }
+            | BondingEvent::InitializeSolStorage
+            | BondingEvent::BuyWrappedSol { .. }
+            | BondingEvent::SellWrappedSol { .. } => None,
+        }
+    }
+}
+
+/// A decoded instruction and where it sits in the transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    /// Index of the outer instruction.
+    pub instruction_index: u32,
+    /// Position within the outer instruction's CPIs, `None` for the outer one.
+    pub inner_index: Option<u32>,
+    pub event: BondingEvent,
+}
+
+/// Decodes every spl-token-bonding instruction in `tx`, outer and inner.
+///
+/// Fails only when the transaction itself is unreadable. An instruction that
+/// cannot be decoded is logged, counted and skipped, so the rest of the
+/// transaction still produces its events.
+pub fn decode_transaction(tx: &SubscribeUpdateTransactionInfo) -> anyhow::Result<Vec<DecodedInstruction>> {
+    let message = tx
+        .transaction
+        .as_ref()
+        .and_then(|transaction| transaction.message.as_ref())
+        .ok_or_else(|| anyhow!("transaction has no message"))?;
+    let keys = account_keys(&message.account_keys, tx.meta.as_ref())?;
+    let program_id = program_id();
+    let is_bonding_program =
+        |program_id_index: u32| keys.get(program_id_index as usize) == Some(&program_id);
+
+    let mut decoded = Vec::new();
+    let mut push = |instruction_index: u32, inner_index: Option<u32>, data: &[u8], accounts: &[u8]| {
+        match decode_instruction(data, accounts, &keys) {
+            Ok(Some(event)) => decoded.push(DecodedInstruction {
+                instruction_index,
+                inner_index,
+                event,
+            }),
+            Ok(None) => {}
+            Err(error) => {
+                metrics::DECODE_FAILURES.with_label_values(&["instruction"]).inc();
+                warn!(instruction_index, inner_index, ?error, "failed to decode instruction, skipping it");
+            }
+        }
+    };
+    for (index, ix) in message.instructions.iter().enumerate() {
+        if is_bonding_program(ix.program_id_index) {
+            push(index as u32, None, &ix.data, &ix.accounts);
+        }
+    }
+    for inner in tx.meta.iter().flat_map(|meta| &meta.inner_instructions) {
+        for (inner_index, ix) in inner.instructions.iter().enumerate() {
+            if is_bonding_program(ix.program_id_index) {
+                push(inner.index, Some(inner_index as u32), &ix.data, &ix.accounts);
+            }
+        }
+    }
+
+    decoded.sort_by_key(|ix| (ix.instruction_index, ix.inner_index));
+    Ok(decoded)
+}
+
+/// Full account list in the order instruction indexes refer to: static keys,
+/// then writable and readonly addresses loaded from lookup tables.
+pub fn account_keys(
+    static_keys: &[Vec<u8>],
+    meta: Option<&TransactionStatusMeta>,
+) -> anyhow::Result<Vec<Pubkey>> {
+    let loaded = meta
+        .into_iter()
+        .flat_map(|meta| meta.loaded_writable_addresses.iter().chain(&meta.loaded_readonly_addresses));
+    static_keys
+        .iter()
+        .chain(loaded)
+        .map(|key| Pubkey::try_from(key.as_slice()).map_err(|_| anyhow!("invalid account key")))
+        .collect()
+}
+
+/// The spl-token-bonding program id as a `solana_sdk` pubkey; anchor pins its
+/// own solana-program version, so the types are not interchangeable.
+pub fn program_id() -> Pubkey {
+    Pubkey::new_from_array(spl_token_bonding::ID.to_bytes())
+}
+
+const INSTRUCTIONS: &[&str] = &[
+    "initialize_sol_storage_v0",
+    "buy_wrapped_sol_v0",
+    "sell_wrapped_sol_v0",
+    "create_curve_v0",
+    "initialize_token_bonding_v0",
+    "close_token_bonding_v0",
+    "transfer_reserves_v0",
+    "transfer_reserves_native_v0",
+    "update_reserve_authority_v0",
+    "update_curve_v0",
+    "update_token_bonding_v0",
+    "buy_v1",
+    "buy_native_v0",
+    "sell_v1",
+    "sell_native_v0",
+];
+
+/// Maps an anchor instruction discriminator, `sha256("global:<name>")[..8]`,
+/// back to the instruction name.
+fn instruction_name(discriminator: &[u8]) -> Option<&'static str> {
+    static SIGHASHES: OnceLock<Vec<([u8; 8], &'static str)>> = OnceLock::new();
+    SIGHASHES
+        .get_or_init(|| {
+            INSTRUCTIONS
+                .iter()
+                .map(|name| {
+                    let mut sighash = [0u8; 8];
+                    sighash.copy_from_slice(&hash(format!("global:{name}").as_bytes()).to_bytes()[..8]);
+                    (sighash, *name)
+                })
+                .collect()
+        })
+        .iter()
+        .find(|(sighash, _)| sighash == discriminator)
+        .map(|(_, name)| *name)
+}
+
+/// Resolves the account at `position` in the instruction's account list.
+struct Accounts<'a> {
+    indexes: &'a [u8],
+    keys: &'a [Pubkey],
+}
+
+impl Accounts<'_> {
+    fn get(&self, position: usize) -> anyhow::Result<Pubkey> {
+        let index = *self
+            .indexes
+            .get(position)
+            .ok_or_else(|| anyhow!("missing account {position}"))?;
+        self.keys
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| anyhow!("account index {index} out of range"))
+    }
+}
+
+// Account positions follow the order of the program's `Accounts` structs.
+// BuyCommonV0 / SellCommonV0 come first in every buy/sell instruction.
+const COMMON_TOKEN_BONDING: usize = 0;
+const COMMON_BASE_MINT: usize = 2;
+const COMMON_TARGET_MINT: usize = 3;
+const COMMON_BASE_STORAGE: usize = 4;
+const COMMON_BASE_ROYALTIES: usize = 5;
+const BUY_COMMON_DESTINATION: usize = 6;
+const BUY_COMMON_TARGET_ROYALTIES: usize = 7;
+const BUY_V1_SOURCE_AUTHORITY: usize = 12;
+const BUY_NATIVE_V0_SOURCE: usize = 10;
+const SELL_COMMON_SOURCE: usize = 6;
+const SELL_COMMON_SOURCE_AUTHORITY: usize = 7;
+const SELL_COMMON_TARGET_ROYALTIES: usize = 8;
+
+fn decode_instruction(data: &[u8], indexes: &[u8], keys: &[Pubkey]) -> anyhow::Result<Option<BondingEvent>> {
+    if data.len() < 8 {
+        return Ok(None);
+    }
+    let (discriminator, mut data) = data.split_at(8);
+    let Some(name) = instruction_name(discriminator) else {
+        return Ok(None);
+    };
+    let accounts = Accounts { indexes, keys };
+
+    let event = match name {
+        "buy_v1" | "buy_native_v0" => {
+            let native = name == "buy_native_v0";
+            let args = args::BuyV0Args::deserialize(&mut data)?;
+            let amount = match (args.buy_with_base, args.buy_target_amount) {
+                (Some(base), _) => BuyAmount::WithBase {
+                    base_amount: base.base_amount,
+                    minimum_target_amount: base.minimum_target_amount,
+                },
+                (None, Some(target)) => BuyAmount::TargetAmount {
+                    target_amount: target.target_amount,
+                    maximum_price: target.maximum_price,
+                },
+                (None, None) => return Err(anyhow!("buy without an amount")),
+            };
+            BondingEvent::Buy {
+                token_bonding: accounts.get(COMMON_TOKEN_BONDING)?,
+                base_mint: accounts.get(COMMON_BASE_MINT)?,
+                target_mint: accounts.get(COMMON_TARGET_MINT)?,
+                base_storage: accounts.get(COMMON_BASE_STORAGE)?,
+                base_royalties: accounts.get(COMMON_BASE_ROYALTIES)?,
+                target_royalties: accounts.get(BUY_COMMON_TARGET_ROYALTIES)?,
+                destination: accounts.get(BUY_COMMON_DESTINATION)?,
+                trader: accounts.get(if native {
+                    BUY_NATIVE_V0_SOURCE
+                } else {
+                    BUY_V1_SOURCE_AUTHORITY
+                })?,
+                native,
+                amount,
+            }
+        }
+        "sell_v1" | "sell_native_v0" => {
+            let args = args::SellV0Args::deserialize(&mut data)?;
+            BondingEvent::Sell {
+                token_bonding: accounts.get(COMMON_TOKEN_BONDING)?,
+                base_mint: accounts.get(COMMON_BASE_MINT)?,
+                target_mint: accounts.get(COMMON_TARGET_MINT)?,
+                base_storage: accounts.get(COMMON_BASE_STORAGE)?,
+                base_royalties: accounts.get(COMMON_BASE_ROYALTIES)?,
+                target_royalties: accounts.get(SELL_COMMON_TARGET_ROYALTIES)?,
+                source: accounts.get(SELL_COMMON_SOURCE)?,
+                trader: accounts.get(SELL_COMMON_SOURCE_AUTHORITY)?,
+                native: name == "sell_native_v0",
+                target_amount: args.target_amount,
+                minimum_price: args.minimum_price,
+            }
+        }
+        // Arguments are not decoded: the resulting state arrives as an account update.
+        "initialize_token_bonding_v0" => BondingEvent::InitializeTokenBonding {
+            payer: accounts.get(0)?,
+            curve: accounts.get(1)?,
+            token_bonding: accounts.get(2)?,
+            base_mint: accounts.get(3)?,
+            target_mint: accounts.get(4)?,
+        },
+        "update_token_bonding_v0" => BondingEvent::UpdateTokenBonding {
+            token_bonding: accounts.get(0)?,
+            general_authority: accounts.get(1)?,
+        },
+        "close_token_bonding_v0" => BondingEvent::CloseTokenBonding {
+            refund: accounts.get(0)?,
+            token_bonding: accounts.get(1)?,
+        },
+        "update_reserve_authority_v0" => {
+            let args = args::UpdateReserveAuthorityV0Args::deserialize(&mut data)?;
+            BondingEvent::UpdateReserveAuthority {
+                token_bonding: accounts.get(0)?,
+                new_reserve_authority: args.new_reserve_authority,
+            }
+        }
+        "update_curve_v0" => {
+            let args = args::UpdateCurveV0Args::deserialize(&mut data)?;
+            BondingEvent::UpdateCurve {
+                token_bonding: accounts.get(0)?,
+                curve: accounts.get(2)?,
+                curve_authority: args.curve_authority,
+            }
+        }
+        "transfer_reserves_v0" | "transfer_reserves_native_v0" => {
+            let args = args::TransferReservesV0Args::deserialize(&mut data)?;
+            BondingEvent::TransferReserves {
+                token_bonding: accounts.get(0)?,
+                destination: accounts.get(5)?,
+                native: name == "transfer_reserves_native_v0",
+                amount: args.amount,
+            }
+        }
+        "create_curve_v0" => BondingEvent::CreateCurve {
+            curve: accounts.get(1)?,
+        },
+        "initialize_sol_storage_v0" => BondingEvent::InitializeSolStorage,
+        "buy_wrapped_sol_v0" => {
+            let args = args::BuyWrappedSolV0Args::deserialize(&mut data)?;
+            BondingEvent::BuyWrappedSol { amount: args.amount }
+        }
+        "sell_wrapped_sol_v0" => {
+            let args = args::SellWrappedSolV0Args::deserialize(&mut data)?;
+            BondingEvent::SellWrappedSol {
+                amount: args.amount,
+                all: args.all,
+            }
+        }
+        other => return Err(anyhow!("{other} is listed in INSTRUCTIONS but has no decoder")),
+    };
+    Ok(Some(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yellowstone_grpc_proto::prelude::{CompiledInstruction, Message, Transaction};
+
+    fn sighash(name: &str) -> Vec<u8> {
+        hash(format!("global:{name}").as_bytes()).to_bytes()[..8].to_vec()
+    }
+
+    /// Distinct keys, so every position resolves to a recognizable account.
+    fn keys() -> Vec<Pubkey> {
+        (0..16).map(|i| Pubkey::new_from_array([i; 32])).collect()
+    }
+
+    fn indexes() -> Vec<u8> {
+        (0..16).collect()
+    }
+
+    fn sell_data() -> Vec<u8> {
+        let mut data = sighash("sell_v1");
+        data.extend(1_000u64.to_le_bytes());
+        data.extend(5u64.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn sighashes_map_back_to_their_instruction() {
+        for name in INSTRUCTIONS {
+            assert_eq!(instruction_name(&sighash(name)), Some(*name));
+        }
+        assert_eq!(instruction_name(&sighash("buy_v0")), None);
+    }
+
+    #[test]
+    fn every_listed_instruction_has_a_decoder() {
+        let keys = keys();
+        for name in INSTRUCTIONS {
+            // Long enough for any argument layout; option tags read as `None`.
+            let mut data = sighash(name);
+            data.extend([0u8; 64]);
+            if let Err(error) = decode_instruction(&data, &indexes(), &keys) {
+                assert!(!error.to_string().contains("no decoder"), "{name}: {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn sell_accounts_follow_sell_common() {
+        let keys = keys();
+        let event = decode_instruction(&sell_data(), &indexes(), &keys).unwrap().unwrap();
+        assert_eq!(
+            event,
+            BondingEvent::Sell {
+                token_bonding: keys[0],
+                base_mint: keys[2],
+                target_mint: keys[3],
+                base_storage: keys[4],
+                base_royalties: keys[5],
+                source: keys[6],
+                trader: keys[7],
+                target_royalties: keys[8],
+                native: false,
+                target_amount: 1_000,
+                minimum_price: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn buy_accounts_follow_buy_common() {
+        let keys = keys();
+        let mut data = sighash("buy_v1");
+        data.push(1);
+        data.extend(2_000u64.to_le_bytes());
+        data.extend(10u64.to_le_bytes());
+        data.push(0);
+        let event = decode_instruction(&data, &indexes(), &keys).unwrap().unwrap();
+        assert_eq!(
+            event,
+            BondingEvent::Buy {
+                token_bonding: keys[0],
+                base_mint: keys[2],
+                target_mint: keys[3],
+                base_storage: keys[4],
+                base_royalties: keys[5],
+                destination: keys[6],
+                target_royalties: keys[7],
+                trader: keys[12],
+                native: false,
+                amount: BuyAmount::WithBase {
+                    base_amount: 2_000,
+                    minimum_target_amount: 10,
+                },
+            }
+        );
+
+        let mut data = sighash("buy_native_v0");
+        data.extend([0, 1]);
+        data.extend(3u64.to_le_bytes());
+        data.extend(4u64.to_le_bytes());
+        match decode_instruction(&data, &indexes(), &keys).unwrap().unwrap() {
+            BondingEvent::Buy { trader, native, amount, .. } => {
+                assert_eq!(trader, keys[10]);
+                assert!(native);
+                assert_eq!(amount, BuyAmount::TargetAmount { target_amount: 3, maximum_price: 4 });
+            }
+            other => panic!("decoded {other:?}"),
+        }
+    }
+
+    #[test]
+    fn missing_accounts_are_errors() {
+        assert!(decode_instruction(&sell_data(), &[0, 1, 2], &keys()).is_err());
+    }
+
+    #[test]
+    fn undecodable_instructions_do_not_drop_the_transaction() {
+        let mut keys = keys();
+        keys.push(program_id());
+        let program_id_index = (keys.len() - 1) as u32;
+        let instruction = |data: Vec<u8>| CompiledInstruction {
+            program_id_index,
+            accounts: indexes(),
+            data,
+        };
+        let tx = SubscribeUpdateTransactionInfo {
+            transaction: Some(Transaction {
+                message: Some(Message {
+                    account_keys: keys.iter().map(|key| key.to_bytes().to_vec()).collect(),
+                    instructions: vec![
+                        // Truncated arguments.
+                        instruction(sighash("sell_v1")),
+                        instruction(sell_data()),
+                    ],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let decoded = decode_transaction(&tx).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].instruction_index, 1);
+    }
+}
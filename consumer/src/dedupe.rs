@@ -0,0 +1,108 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+};
+
+use crate::point::Point;
+
+/// IDs remembered by default; a few minutes of bonding activity, which
+/// covers reconnect overlaps and backfills of recent gaps.
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// Bounded set of recently seen `(measurement, id)` pairs, oldest evicted
+/// first. Stores 64-bit hashes rather than the IDs themselves.
+#[derive(Debug)]
+pub struct RecentIds {
+    capacity: usize,
+    order: VecDeque<u64>,
+    seen: HashSet<u64>,
+}
+
+impl RecentIds {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            seen: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Points without an ID are never considered seen.
+    pub fn contains(&self, point: &Point) -> bool {
+        !point.id.is_empty() && self.seen.contains(&key(point))
+    }
+
+    /// Remembers `point`, returning whether it was new.
+    pub fn insert(&mut self, point: &Point) -> bool {
+        if point.id.is_empty() {
+            return true;
+        }
+        let key = key(point);
+        if !self.seen.insert(key) {
+            return false;
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+impl Default for RecentIds {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+fn key(point: &Point) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    point.measurement.hash(&mut hasher);
+    point.id.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn point(measurement: &str, id: &str) -> Point {
+        Point::new(measurement, id, Utc::now())
+    }
+
+    #[test]
+    fn remembers_ids_per_measurement() {
+        let mut recent = RecentIds::new(10);
+        assert!(recent.insert(&point("bonding_trades", "sig:0")));
+        assert!(recent.contains(&point("bonding_trades", "sig:0")));
+        assert!(!recent.insert(&point("bonding_trades", "sig:0")));
+        assert!(!recent.contains(&point("bonding_events", "sig:0")));
+        assert!(recent.insert(&point("bonding_events", "sig:0")));
+    }
+
+    #[test]
+    fn points_without_an_id_are_always_new() {
+        let mut recent = RecentIds::new(10);
+        assert!(recent.insert(&point("bonding_trades", "")));
+        assert!(recent.insert(&point("bonding_trades", "")));
+        assert!(!recent.contains(&point("bonding_trades", "")));
+    }
+
+    #[test]
+    fn evicts_the_oldest_id_past_capacity() {
+        let mut recent = RecentIds::new(2);
+        for id in ["a", "b", "c"] {
+            recent.insert(&point("bonding_trades", id));
+        }
+        assert!(!recent.contains(&point("bonding_trades", "a")));
+        assert!(recent.contains(&point("bonding_trades", "b")));
+        assert!(recent.contains(&point("bonding_trades", "c")));
+        // Re-inserting a remembered ID does not refresh or duplicate it.
+        assert!(!recent.insert(&point("bonding_trades", "b")));
+        recent.insert(&point("bonding_trades", "d"));
+        assert!(!recent.contains(&point("bonding_trades", "b")));
+    }
+}
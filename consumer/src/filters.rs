@@ -0,0 +1,418 @@
+//! Named subscription filters. Every filter becomes one or more entries of the
+//! geyser `SubscribeRequest`, keyed by the filter name, and updates come back
+//! tagged with the keys they matched, which is how they are routed to the
+//! filter's decoder.
+
+use anchor_lang::Discriminator;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use spl_token_bonding::state::TokenBondingV0;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+};
+use tokio::sync::watch;
+use yellowstone_grpc_proto::prelude::{
+    subscribe_request_filter_accounts_filter::Filter as AccountsFilterDataOneof,
+    subscribe_request_filter_accounts_filter_memcmp::Data as AccountsFilterMemcmpOneof,
+    CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts,
+    SubscribeRequestFilterAccountsFilter, SubscribeRequestFilterAccountsFilterMemcmp,
+    SubscribeRequestFilterBlocksMeta, SubscribeRequestFilterSlots,
+    SubscribeRequestFilterTransactions,
+};
+
+/// `TokenBondingV0` starts with `base_mint` then `target_mint`, right after
+/// the 8 byte anchor discriminator.
+const BASE_MINT_OFFSET: u64 = 8;
+const TARGET_MINT_OFFSET: u64 = 40;
+
+/// Block metas carry the block time used to timestamp points and slot
+/// updates drive commitment tracking; both are subscribed under fixed keys
+/// regardless of the filters.
+const BLOCKS_META_KEY: &str = "block_meta";
+const SLOTS_KEY: &str = "slots";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+    /// Every bonding account owned by, and transaction invoking, the programs.
+    Program,
+    /// Specific bonding accounts and the transactions touching them.
+    Account,
+    /// Bondings with these base or target mints and transactions touching
+    /// the mints.
+    Mint,
+    /// Transactions signed by or touching these wallets.
+    Wallet,
+}
+
+/// How updates matched by a filter are turned into points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DecoderKind {
+    /// Only the `bonding_transactions` record, no instruction decoding.
+    Raw,
+    /// Full spl-token-bonding decoding: account state, events and trades.
+    #[default]
+    Bonding,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: FilterKind,
+    #[serde(with = "pubkeys")]
+    pub pubkeys: Vec<Pubkey>,
+    #[serde(default)]
+    pub decoder: DecoderKind,
+}
+
+impl FilterSpec {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.name.is_empty()
+            || self.name.contains('#')
+            || self.name == BLOCKS_META_KEY
+            || self.name == SLOTS_KEY
+        {
+            anyhow::bail!("invalid filter name {:?}", self.name);
+        }
+        if self.pubkeys.is_empty() {
+            anyhow::bail!("filter {:?} has no pubkeys", self.name);
+        }
+        Ok(())
+    }
+
+    fn pubkey_strings(&self) -> Vec<String> {
+        self.pubkeys.iter().map(Pubkey::to_string).collect()
+    }
+
+    /// TokenBondingV0 accounts matching `filters`, told apart from other
+    /// accounts by the anchor discriminator.
+    fn bonding_accounts(
+        &self,
+        filters: Vec<SubscribeRequestFilterAccountsFilter>,
+    ) -> SubscribeRequestFilterAccounts {
+        let mut all = vec![memcmp(0, TokenBondingV0::discriminator().to_vec())];
+        all.extend(filters);
+        SubscribeRequestFilterAccounts {
+            filters: all,
+            ..Default::default()
+        }
+    }
+
+    /// Account entries keyed by subscription key; empty for wallet filters
+    /// since wallets own no bonding accounts.
+    fn accounts(&self) -> Vec<(String, SubscribeRequestFilterAccounts)> {
+        match self.kind {
+            FilterKind::Program => vec![(
+                self.name.clone(),
+                SubscribeRequestFilterAccounts {
+                    owner: self.pubkey_strings(),
+                    ..self.bonding_accounts(vec![])
+                },
+            )],
+            FilterKind::Account => vec![(
+                self.name.clone(),
+                SubscribeRequestFilterAccounts {
+                    account: self.pubkey_strings(),
+                    ..self.bonding_accounts(vec![])
+                },
+            )],
+            // Memcmp filters within one entry are ANDed, so every mint gets
+            // an entry per side, keyed `<name>#<mint>#target` and `#base`.
+            FilterKind::Mint => self
+                .pubkeys
+                .iter()
+                .flat_map(|mint| {
+                    let bytes = mint.to_bytes().to_vec();
+                    [
+                        (
+                            format!("{}#{mint}#target", self.name),
+                            self.bonding_accounts(vec![memcmp(TARGET_MINT_OFFSET, bytes.clone())]),
+                        ),
+                        (
+                            format!("{}#{mint}#base", self.name),
+                            self.bonding_accounts(vec![memcmp(BASE_MINT_OFFSET, bytes)]),
+                        ),
+                    ]
+                })
+                .collect(),
+            FilterKind::Wallet => vec![],
+        }
+    }
+
+    fn transactions(&self) -> SubscribeRequestFilterTransactions {
+        SubscribeRequestFilterTransactions {
+            vote: Some(false),
+            failed: Some(false),
+            signature: None,
+            account_include: self.pubkey_strings(),
+            account_exclude: vec![],
+            account_required: vec![],
+        }
+    }
+}
+
+fn memcmp(offset: u64, bytes: Vec<u8>) -> SubscribeRequestFilterAccountsFilter {
+    SubscribeRequestFilterAccountsFilter {
+        filter: Some(AccountsFilterDataOneof::Memcmp(SubscribeRequestFilterAccountsFilterMemcmp {
+            offset,
+            data: Some(AccountsFilterMemcmpOneof::Bytes(bytes)),
+        })),
+    }
+}
+
+/// Filter name a subscription key belongs to.
+pub fn filter_name(key: &str) -> &str {
+    key.split('#').next().unwrap_or(key)
+}
+
+/// Live set of filters. Changes are announced on a watch channel so the
+/// stream can resend its `SubscribeRequest` without reconnecting.
+pub struct FilterRegistry {
+    filters: RwLock<BTreeMap<String, FilterSpec>>,
+    changed: watch::Sender<u64>,
+}
+
+impl FilterRegistry {
+    pub fn new(filters: Vec<FilterSpec>) -> anyhow::Result<Self> {
+        let mut by_name = BTreeMap::new();
+        for filter in filters {
+            filter.validate()?;
+            let name = filter.name.clone();
+            if by_name.insert(name.clone(), filter).is_some() {
+                anyhow::bail!("duplicate filter name {name:?}");
+            }
+        }
+        Ok(Self {
+            filters: RwLock::new(by_name),
+            changed: watch::channel(0).0,
+        })
+    }
+
+    pub fn list(&self) -> Vec<FilterSpec> {
+        self.filters.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.filters.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Adds or replaces the filter with the same name.
+    pub fn upsert(&self, filter: FilterSpec) -> anyhow::Result<()> {
+        filter.validate()?;
+        self.filters.write().unwrap().insert(filter.name.clone(), filter);
+        self.changed.send_modify(|version| *version += 1);
+        Ok(())
+    }
+
+    /// Returns whether a filter with that name existed.
+    pub fn remove(&self, name: &str) -> bool {
+        let removed = self.filters.write().unwrap().remove(name).is_some();
+        if removed {
+            self.changed.send_modify(|version| *version += 1);
+        }
+        removed
+    }
+
+    /// Fires whenever the filter set changes.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changed.subscribe()
+    }
+
+    /// Always at `processed`: holding points back until the configured
+    /// commitment is done by [`crate::commitment::SlotTracker`].
+    pub fn request(&self) -> SubscribeRequest {
+        let filters = self.filters.read().unwrap();
+        let mut accounts = HashMap::new();
+        let mut transactions = HashMap::new();
+        for filter in filters.values() {
+            accounts.extend(filter.accounts());
+            transactions.insert(filter.name.clone(), filter.transactions());
+        }
+        let mut blocks_meta = HashMap::new();
+        blocks_meta.insert(BLOCKS_META_KEY.to_string(), SubscribeRequestFilterBlocksMeta {});
+        let mut slots = HashMap::new();
+        slots.insert(SLOTS_KEY.to_string(), SubscribeRequestFilterSlots::default());
+
+        SubscribeRequest {
+            accounts,
+            transactions,
+            blocks_meta,
+            slots,
+            commitment: Some(CommitmentLevel::Processed as i32),
+            ..Default::default()
+        }
+    }
+
+    /// Strongest decoder among the filters an update matched, `None` when
+    /// every matching filter has been removed since.
+    pub fn decoder_for(&self, keys: &[String]) -> Option<DecoderKind> {
+        let filters = self.filters.read().unwrap();
+        keys.iter()
+            .filter_map(|key| filters.get(filter_name(key)))
+            .map(|filter| filter.decoder)
+            .max()
+    }
+
+    /// Addresses whose signature history covers the filters, with the decoder
+    /// to apply; used to backfill over RPC.
+    pub fn backfill_addresses(&self) -> Vec<(Pubkey, DecoderKind)> {
+        let filters = self.filters.read().unwrap();
+        let mut addresses: BTreeMap<Pubkey, DecoderKind> = BTreeMap::new();
+        for filter in filters.values() {
+            for pubkey in &filter.pubkeys {
+                let decoder = addresses.entry(*pubkey).or_insert(filter.decoder);
+                *decoder = (*decoder).max(filter.decoder);
+            }
+        }
+        addresses.into_iter().collect()
+    }
+}
+
+/// Pubkeys as base58 strings in config files and admin requests.
+mod pubkeys {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use solana_sdk::pubkey::Pubkey;
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(pubkeys: &[Pubkey], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(pubkeys.iter().map(Pubkey::to_string))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Pubkey>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|pubkey| {
+                Pubkey::from_str(pubkey)
+                    .map_err(|_| D::Error::custom(format!("invalid pubkey {pubkey:?}")))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(seed: u8) -> Pubkey {
+        Pubkey::new_from_array([seed; 32])
+    }
+
+    fn filter(name: &str, kind: FilterKind, pubkeys: &[Pubkey], decoder: DecoderKind) -> FilterSpec {
+        FilterSpec {
+            name: name.to_string(),
+            kind,
+            pubkeys: pubkeys.to_vec(),
+            decoder,
+        }
+    }
+
+    /// `(offset, bytes)` of every memcmp filter of an entry, in order.
+    fn memcmps(entry: &SubscribeRequestFilterAccounts) -> Vec<(u64, Vec<u8>)> {
+        entry
+            .filters
+            .iter()
+            .map(|filter| match &filter.filter {
+                Some(AccountsFilterDataOneof::Memcmp(SubscribeRequestFilterAccountsFilterMemcmp {
+                    offset,
+                    data: Some(AccountsFilterMemcmpOneof::Bytes(bytes)),
+                })) => (*offset, bytes.clone()),
+                other => panic!("unexpected filter {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bonding_accounts_match_the_discriminator_first() {
+        let discriminator = (0, TokenBondingV0::discriminator().to_vec());
+        let accounts = filter("bondings", FilterKind::Account, &[key(1)], DecoderKind::Bonding).accounts();
+        let [(name, entry)] = &accounts[..] else {
+            panic!("expected one entry, got {accounts:?}");
+        };
+        assert_eq!(name, "bondings");
+        assert_eq!(entry.account, vec![key(1).to_string()]);
+        assert_eq!(memcmps(entry), vec![discriminator.clone()]);
+
+        let accounts = filter("program", FilterKind::Program, &[key(2)], DecoderKind::Bonding).accounts();
+        assert_eq!(accounts[0].1.owner, vec![key(2).to_string()]);
+        assert_eq!(memcmps(&accounts[0].1), vec![discriminator]);
+    }
+
+    #[test]
+    fn mints_get_one_entry_per_side() {
+        let mint = key(3);
+        let accounts: BTreeMap<String, SubscribeRequestFilterAccounts> =
+            filter("mints", FilterKind::Mint, &[mint], DecoderKind::Bonding).accounts().into_iter().collect();
+        let discriminator = (0, TokenBondingV0::discriminator().to_vec());
+
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(
+            memcmps(&accounts[&format!("mints#{mint}#target")]),
+            vec![discriminator.clone(), (TARGET_MINT_OFFSET, mint.to_bytes().to_vec())]
+        );
+        assert_eq!(
+            memcmps(&accounts[&format!("mints#{mint}#base")]),
+            vec![discriminator, (BASE_MINT_OFFSET, mint.to_bytes().to_vec())]
+        );
+    }
+
+    #[test]
+    fn wallets_subscribe_to_transactions_only() {
+        let wallet = filter("wallets", FilterKind::Wallet, &[key(4)], DecoderKind::Raw);
+        assert!(wallet.accounts().is_empty());
+        let transactions = wallet.transactions();
+        assert_eq!(transactions.account_include, vec![key(4).to_string()]);
+        assert_eq!((transactions.vote, transactions.failed), (Some(false), Some(false)));
+    }
+
+    #[test]
+    fn keys_map_back_to_their_filter() {
+        assert_eq!(filter_name("bonding"), "bonding");
+        assert_eq!(filter_name(&format!("mints#{}#target", key(3))), "mints");
+        assert_eq!(filter_name(""), "");
+    }
+
+    #[test]
+    fn validation_rejects_reserved_and_ambiguous_names() {
+        let named = |name: &str| filter(name, FilterKind::Account, &[key(1)], DecoderKind::Bonding);
+        for name in ["", "mints#1", BLOCKS_META_KEY, SLOTS_KEY] {
+            assert!(named(name).validate().is_err(), "{name:?}");
+            assert!(FilterRegistry::new(vec![named(name)]).is_err(), "{name:?}");
+        }
+        assert!(named("bondings").validate().is_ok());
+        assert!(filter("empty", FilterKind::Account, &[], DecoderKind::Bonding).validate().is_err());
+    }
+
+    #[test]
+    fn the_strongest_matching_decoder_wins() {
+        let registry = FilterRegistry::new(vec![
+            filter("raw", FilterKind::Wallet, &[key(1)], DecoderKind::Raw),
+            filter("mints", FilterKind::Mint, &[key(2)], DecoderKind::Bonding),
+        ])
+        .unwrap();
+        let mint_key = format!("mints#{}#base", key(2));
+
+        assert_eq!(registry.decoder_for(&["raw".to_string()]), Some(DecoderKind::Raw));
+        assert_eq!(registry.decoder_for(&["raw".to_string(), mint_key.clone()]), Some(DecoderKind::Bonding));
+        assert_eq!(registry.decoder_for(&["removed".to_string()]), None);
+
+        registry.remove("mints");
+        assert_eq!(registry.decoder_for(&["raw".to_string(), mint_key]), Some(DecoderKind::Raw));
+    }
+
+    #[test]
+    fn backfill_addresses_are_merged_across_filters() {
+        let registry = FilterRegistry::new(vec![
+            filter("raw", FilterKind::Wallet, &[key(1), key(2)], DecoderKind::Raw),
+            filter("bondings", FilterKind::Account, &[key(2), key(3)], DecoderKind::Bonding),
+        ])
+        .unwrap();
+        assert_eq!(
+            registry.backfill_addresses(),
+            vec![(key(1), DecoderKind::Raw), (key(2), DecoderKind::Bonding), (key(3), DecoderKind::Bonding)]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Log setup: `tracing` events as text or JSON lines on stderr, filtered by
+//! `log.level` (an `EnvFilter` directive such as `info,consumer=debug`).
+//!
+//! Every configured secret is scrubbed from the rendered line before it is
+//! written, which also covers secrets that end up inside error messages, e.g.
+//! an RPC URL with an API key echoed back by reqwest.
+
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tracing_subscriber::{fmt::MakeWriter, EnvFilter};
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+/// Installs the global subscriber. `secrets` are replaced by `[redacted]`
+/// wherever they appear in a log line.
+pub fn init(config: &LogConfig, secrets: Vec<String>) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_new(&config.level)?;
+    let writer = RedactingWriter {
+        secrets: Arc::new(secrets),
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer);
+    match config.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).with_current_span(true).try_init(),
+    }
+    .map_err(|error| anyhow::anyhow!("failed to install logger: {error}"))
+}
+
+/// Parts of a secret worth scrubbing on their own: URLs are often logged in
+/// a normalized form, so their password and query values are listed too.
+pub fn secret_fragments(secret: &str) -> Vec<String> {
+    let mut fragments = vec![secret.to_string()];
+    if let Ok(url) = reqwest::Url::parse(secret) {
+        fragments.extend(url.password().map(str::to_string));
+        fragments.extend(url.query_pairs().map(|(_, value)| value.into_owned()));
+        fragments.push(url.to_string());
+    }
+    // Short fragments would mangle unrelated text.
+    fragments.retain(|fragment| fragment.len() >= 6);
+    fragments
+}
+
+#[derive(Clone)]
+struct RedactingWriter {
+    secrets: Arc<Vec<String>>,
+}
+
+impl<'a> MakeWriter<'a> for RedactingWriter {
+    type Writer = RedactingLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingLine {
+            secrets: self.secrets.clone(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// Buffers one formatted event and writes it, scrubbed, when dropped.
+struct RedactingLine {
+    secrets: Arc<Vec<String>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for RedactingLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RedactingLine {
+    fn drop(&mut self) {
+        let mut line = String::from_utf8_lossy(&self.buffer).into_owned();
+        for secret in self.secrets.iter() {
+            if line.contains(secret.as_str()) {
+                line = line.replace(secret.as_str(), REDACTED);
+            }
+        }
+        let _ = io::stderr().lock().write_all(line.as_bytes());
+    }
+}
@@ -1,273 +1,865 @@
-use influxdb::{Client, InfluxDbWriteable, WriteQuery};
-use yellowstone_grpc_client::{GeyserGrpcClient, GeyserGrpcClientError};
-use yellowstone_grpc_proto::prelude::*;
+mod admin;
+mod backfill;
+mod backoff;
+mod checkpoint;
+mod commitment;
+mod config;
+mod dedupe;
+mod decoder;
+mod filters;
+mod logging;
+mod metrics;
+mod point;
+mod recording;
+mod schema;
+mod sink;
+mod slot_clock;
+mod status;
+mod trades;
+mod writer;
+
+use anchor_lang::AccountDeserialize;
 use chrono::Utc;
-use bs58;
-use     solana_transaction_status::{EncodedTransactionWithStatusMeta, UiTransactionEncoding};
-use     futures::{future::TryFutureExt, sink::SinkExt, stream::StreamExt};
-use solana_sdk::{pubkey::Pubkey, signature::Signature, transaction::TransactionError};
-use std::time::Duration;
+use futures::{future::FutureExt, sink::SinkExt, stream::StreamExt};
+use solana_sdk::pubkey::Pubkey;
 use spl_token_bonding::state::TokenBondingV0;
-use anchor_lang::AccountDeserialize;
-use std::collections::HashMap;
-use tonic::transport::channel::ClientTlsConfig;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    task::JoinHandle,
+};
+use tracing::{debug, debug_span, error, field, info, info_span, warn, Instrument, Span};
+use yellowstone_grpc_client::GeyserGrpcClient;
 use yellowstone_grpc_proto::prelude::{
-    subscribe_request_filter_accounts_filter::Filter as AccountsFilterDataOneof,
-    subscribe_request_filter_accounts_filter_memcmp::Data as AccountsFilterMemcmpOneof,
-    subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest,
-    SubscribeRequestAccountsDataSlice, SubscribeRequestFilterAccounts,
-    SubscribeRequestFilterAccountsFilter, SubscribeRequestFilterAccountsFilterMemcmp,
-    SubscribeRequestFilterBlocks, SubscribeRequestFilterBlocksMeta,
-    SubscribeRequestFilterEntry, SubscribeRequestFilterSlots,
-    SubscribeRequestFilterTransactions, SubscribeRequestPing, SubscribeUpdateAccount,
-    SubscribeUpdateTransaction, SubscribeUpdateTransactionStatus,
+    subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest, SubscribeRequestPing, SubscribeUpdate,
+    SubscribeUpdateAccount, SubscribeUpdateBlockMeta, SubscribeUpdateSlot, SubscribeUpdateTransaction,
+    SubscribeUpdateTransactionInfo,
 };
-type TransactionsFilterMap = HashMap<String, SubscribeRequestFilterTransactions>;
-
-#[derive(InfluxDbWriteable)]
-struct AccountUpdate {
-    time: influxdb::Timestamp,
-    base_mint: String,
-    target_mint: String,
-    general_authority: Option<String>,
-    reserve_authority: Option<String>,
-    curve_authority: Option<String>,
-    base_storage: String,
-    buy_base_royalties: String,
-    buy_target_royalties: String,
-    sell_base_royalties: String,
-    sell_target_royalties: String,
-    buy_base_royalty_percentage: u32,
-    buy_target_royalty_percentage: u32,
-    sell_base_royalty_percentage: u32,
-    sell_target_royalty_percentage: u32,
-    curve: String,
-    mint_cap: Option<u64>,
-    purchase_cap: Option<u64>,
-    go_live_unix_time: i64,
-    freeze_buy_unix_time: Option<i64>,
-    created_at_unix_time: i64,
-    buy_frozen: bool,
-    sell_frozen: bool,
-    index: u16,
-    bump_seed: u8,
-    base_storage_bump_seed: u8,
-    target_mint_authority_bump_seed: u8,
-    base_storage_authority_bump_seed: Option<u8>,
-    reserve_balance_from_bonding: u64,
-    supply_from_bonding: u64,
-    ignore_external_reserve_changes: bool,
-    ignore_external_supply_changes: bool,
-}
-use     std::{env, fmt, fs::File, sync::Arc};
 
-#[allow(dead_code)]
-pub struct TransactionPretty {
-    slot: u64,
-    signature: Signature,
-    is_vote: bool,
-    tx: EncodedTransactionWithStatusMeta,
-}
+use backfill::Backfill;
+use backoff::Backoff;
+use checkpoint::{Checkpoint, CheckpointStore, FilterCheckpoint};
+use commitment::{SlotEvents, SlotTracker};
+use config::{Config, GeyserConfig};
+use dedupe::RecentIds;
+use decoder::{account_keys, decode_transaction};
+use filters::{filter_name, DecoderKind, FilterRegistry};
+use point::Point;
+use recording::Recorder;
+use schema::{
+    account_update_point, event_point, orphaned_slot_point, point_time, restamp, trade_point,
+    transaction_point, transaction_sequence,
+};
+use slot_clock::SlotClock;
+use status::StreamStatus;
+use trades::compute_trade;
+use writer::{Receipt, Sinks};
 
-impl fmt::Debug for TransactionPretty {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        struct TxWrap<'a>(&'a EncodedTransactionWithStatusMeta);
-        impl<'a> fmt::Debug for TxWrap<'a> {
-            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-                let serialized = serde_json::to_string(self.0).expect("failed to serialize");
-                fmt::Display::fmt(&serialized, f)
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            // The logger is configured from this file, so it is not up yet.
+            eprintln!("{error:#}");
+            std::process::exit(2);
+        }
+    };
+    logging::init(&config.log, config.secrets())?;
+
+    let sinks = config
+        .sinks
+        .iter()
+        .map(|section| section.to_sink_config().build())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (writer, writer_tasks) = Sinks::spawn(sinks, &config.writer)?;
+
+    let registry = Arc::new(FilterRegistry::new(config.filters())?);
+    let backfill = Arc::new(Backfill::new(
+        config.rpc_url().to_string(),
+        config.geyser.commitment,
+        registry.clone(),
+    ));
+    // A replay must not move the live consumer's checkpoint, nor backfill
+    // from it.
+    let checkpoint = match config.replay {
+        Some(_) => CheckpointStore::disabled(),
+        None => CheckpointStore::new(config.checkpoint_path.clone()),
+    };
+    let mut state = StreamState::new(
+        checkpoint.load().await?,
+        config.geyser.commitment.into(),
+        &registry.names(),
+    );
+
+    if let (Some(listen), Some(token)) = (config.admin.listen, config.admin.token.clone()) {
+        let admin = admin::AdminState {
+            token,
+            registry: registry.clone(),
+            sinks: writer.clone(),
+            backfill: backfill.clone(),
+            status: state.status.clone(),
+        };
+        tokio::spawn(async move {
+            if let Err(error) = admin::serve(listen, admin).await {
+                error!(?error, "admin endpoint failed");
+            }
+        });
+    }
+    if let Some(listen) = config.metrics.listen {
+        let (sinks, status) = (writer.clone(), state.status.clone());
+        tokio::spawn(async move {
+            if let Err(error) = metrics::serve(listen, sinks, status).await {
+                error!(?error, "metrics endpoint failed");
+            }
+        });
+    }
+    let (shutdown_tx, mut shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("shutdown requested");
+        shutdown_tx.send_replace(true);
+    });
+
+    let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(60));
+    let stream_ok = if let Some(replay) = &config.replay {
+        let updates = recording::replay(&replay.path, replay.speed, config.geyser.ping_interval).await?;
+        let result = geyser_subscribe(
+            futures::sink::drain(),
+            Box::pin(updates),
+            &config.geyser,
+            &registry,
+            &mut state,
+            &mut backoff,
+            &checkpoint,
+            &backfill,
+            &writer,
+            None,
+            shutdown.clone(),
+        )
+        .await;
+        match &result {
+            Ok(()) => info!("replay finished"),
+            Err(error) => error!(?error, "replay failed"),
+        }
+        result.is_ok()
+    } else {
+        let mut recorder = config
+            .record
+            .dir
+            .clone()
+            .map(|dir| Recorder::new(dir, &config.record));
+        loop {
+            let result = match connect(&config.geyser).await {
+                Ok(mut client) => match client.subscribe().await {
+                    Ok((subscribe_tx, stream)) => {
+                        geyser_subscribe(
+                            subscribe_tx,
+                            stream,
+                            &config.geyser,
+                            &registry,
+                            &mut state,
+                            &mut backoff,
+                            &checkpoint,
+                            &backfill,
+                            &writer,
+                            recorder.as_mut(),
+                            shutdown.clone(),
+                        )
+                        .await
+                    }
+                    Err(error) => Err(error.into()),
+                },
+                Err(error) => Err(error),
+            };
+            match result {
+                Ok(()) if *shutdown.borrow() => break,
+                Ok(()) => warn!("stream closed by server"),
+                Err(error) => warn!(?error, "stream error"),
+            }
+            if *shutdown.borrow() {
+                break;
+            }
+
+            state.status.set_connected(false);
+            state.reconnects += 1;
+            metrics::RECONNECTS.inc();
+            state.status.set_reconnects(state.reconnects);
+            let delay = backoff.next_delay();
+            info!(
+                ?delay,
+                attempt = backoff.attempt(),
+                last_slot = ?state.last_slot,
+                "reconnecting"
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            }
+        }
+        if let Some(recorder) = recorder.as_mut() {
+            if let Err(error) = recorder.finish().await {
+                warn!(?error, "failed to finish recording");
             }
         }
+        true
+    };
 
-        f.debug_struct("TransactionPretty")
-            .field("slot", &self.slot)
-            .field("signature", &self.signature)
-            .field("is_vote", &self.is_vote)
-            .field("tx", &TxWrap(&self.tx))
-            .finish()
+    state.status.set_connected(false);
+    let mut clean = drain(&writer, writer_tasks, config.writer.drain_timeout).await && stream_ok;
+    // The writers are done, so everything written is settled. Points still
+    // held for commitment or their block time were never written; the
+    // checkpoint stays before them and they are backfilled on restart.
+    if let Err(error) = state.save_settled(&writer, &checkpoint).await {
+        error!(?error, "failed to save checkpoint");
+        clean = false;
+    }
+    info!(last_slot = ?state.last_slot, clean, "stopped");
+    if !clean {
+        std::process::exit(1);
     }
+    Ok(())
 }
 
-impl From<SubscribeUpdateTransaction> for TransactionPretty {
-    fn from(SubscribeUpdateTransaction { transaction, slot }: SubscribeUpdateTransaction) -> Self {
-        let tx = transaction.expect("should be defined");
-        Self {
-            slot,
-            signature: Signature::try_from(tx.signature.as_slice()).expect("valid signature"),
-            is_vote: tx.is_vote,
-            tx: yellowstone_grpc_proto::convert_from::create_tx_with_meta(tx)
-                .expect("valid tx with meta")
-                .encode(UiTransactionEncoding::Base64, Some(u8::MAX), true)
-                .expect("failed to encode"),
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(error) => {
+            warn!(?error, "cannot listen for SIGTERM, only SIGINT stops the consumer");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
         }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
     }
 }
 
+/// Stops the writers and waits up to `timeout` for them to flush. Returns
+/// whether every writer finished in time.
+async fn drain(writer: &Sinks, tasks: Vec<JoinHandle<()>>, timeout: Duration) -> bool {
+    info!(?timeout, "draining sinks");
+    writer.close();
+    match tokio::time::timeout(timeout, futures::future::join_all(tasks)).await {
+        Ok(results) => {
+            let mut clean = true;
+            for error in results.into_iter().filter_map(Result::err) {
+                error!(?error, "writer task failed");
+                clean = false;
+            }
+            clean
+        }
+        Err(_) => {
+            error!(?timeout, "sinks did not drain in time");
+            false
+        }
+    }
+}
 
+/// Points decoded from one geyser update, held until their slot reaches the
+/// configured commitment.
 #[derive(Debug)]
-#[allow(dead_code)]
-pub struct AccountPretty {
-    is_startup: bool,
-    slot: u64,
-    pubkey: Pubkey,
-    lamports: u64,
-    owner: Pubkey,
-    executable: bool,
-    rent_epoch: u64,
-    data: String,
-    write_version: u64,
-    txn_signature: String,
+struct HeldUpdate {
+    /// Names of the filters the update matched.
+    filters: BTreeSet<String>,
+    signature: Option<String>,
+    points: Vec<Point>,
 }
 
-impl From<SubscribeUpdateAccount> for AccountPretty {
-    fn from(
-        SubscribeUpdateAccount {
-            is_startup,
-            slot,
-            account,
-        }: SubscribeUpdateAccount,
-    ) -> Self {
-        let account = account.expect("should be defined");
+/// Released slots wait this many slots behind the newest block meta for
+/// their own before they are written with an extrapolated time.
+const MAX_BLOCK_TIME_WAIT_SLOTS: u64 = 64;
+
+/// Bounds of the delay between attempts of a failing backfill.
+const BACKFILL_RETRY_MIN: Duration = Duration::from_secs(1);
+const BACKFILL_RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// A backfill of slots the stream missed; the checkpoint cannot move past
+/// `after` until its points are settled.
+#[derive(Debug)]
+struct Gap {
+    after: u64,
+    task: Option<JoinHandle<Receipt>>,
+    receipt: Option<Receipt>,
+}
+
+impl Gap {
+    /// Backfills retry until they succeed, so only an aborted or panicked one
+    /// never settles, which keeps the checkpoint before the gap so the next
+    /// start retries it.
+    fn settled(&mut self, writer: &Sinks) -> bool {
+        if self.task.as_ref().map_or(false, JoinHandle::is_finished) {
+            let task = self.task.take().expect("checked above");
+            self.receipt = task.now_or_never().and_then(Result::ok);
+        }
+        self.receipt.as_ref().map_or(false, |receipt| writer.settled(receipt))
+    }
+}
+
+/// Progress of the geyser stream that survives reconnects.
+#[derive(Debug)]
+struct StreamState {
+    /// Highest slot whose points were handed to the sinks.
+    last_slot: Option<u64>,
+    reconnects: u64,
+    slot_clock: SlotClock,
+    /// Updates waiting for their slot to reach the configured commitment.
+    slots: SlotTracker<HeldUpdate>,
+    /// Released slots waiting for their block meta, so that they are written
+    /// with the same time as their backfilled copies.
+    untimed: BTreeMap<u64, Vec<HeldUpdate>>,
+    /// Read-only view for the admin endpoint.
+    status: Arc<StreamStatus>,
+    /// Checkpoint as of `last_slot`, not necessarily sunk yet.
+    released: Checkpoint,
+    /// Released checkpoints waiting for the sinks, oldest first.
+    unsettled: VecDeque<(Receipt, Checkpoint)>,
+    gaps: Vec<Gap>,
+    /// Where the first backfill starts, taken on the first stream open.
+    resume_after: Option<u64>,
+    /// Points already handed to the sinks; a reconnect replays the last
+    /// slots before the server catches up.
+    recent: RecentIds,
+}
+
+impl StreamState {
+    /// Current `filters` resume from their own checkpoint, so one removed for
+    /// a while and added back is backfilled from where it stopped; filters
+    /// the checkpoint does not know start at its global slot.
+    fn new(checkpoint: Option<Checkpoint>, commitment: CommitmentLevel, filters: &[String]) -> Self {
+        let resume_after = checkpoint.as_ref().map(|checkpoint| {
+            filters
+                .iter()
+                .filter_map(|name| checkpoint.filters.get(name))
+                .map(|filter| filter.slot)
+                .fold(checkpoint.slot, u64::min)
+        });
         Self {
-            is_startup,
-            slot,
-            pubkey: Pubkey::try_from(account.pubkey).expect("valid pubkey"),
-            lamports: account.lamports,
-            owner: Pubkey::try_from(account.owner).expect("valid pubkey"),
-            executable: account.executable,
-            rent_epoch: account.rent_epoch,
-            data: hex::encode(account.data),
-            write_version: account.write_version,
-            txn_signature: bs58::encode(account.txn_signature.unwrap_or_default()).into_string(),
+            last_slot: checkpoint.as_ref().map(|checkpoint| checkpoint.slot),
+            reconnects: 0,
+            slot_clock: SlotClock::default(),
+            slots: SlotTracker::new(commitment),
+            untimed: BTreeMap::new(),
+            status: Arc::default(),
+            released: checkpoint.unwrap_or_default(),
+            unsettled: VecDeque::new(),
+            gaps: Vec::new(),
+            resume_after,
+            recent: RecentIds::default(),
+        }
+    }
+
+    /// Writes dead-fork markers and queues released slots until their block
+    /// time is known, then writes what is ready.
+    async fn apply(
+        &mut self,
+        events: SlotEvents<HeldUpdate>,
+        registry: &FilterRegistry,
+        writer: &Sinks,
+        checkpoint: &CheckpointStore,
+    ) -> anyhow::Result<()> {
+        for slot in &events.discarded {
+            debug!(slot, "dropping points of skipped slot");
+        }
+        for slot in events.dead {
+            if self.untimed.remove(&slot).is_some() {
+                debug!(slot, "dropping points of dead slot before writing them");
+                continue;
+            }
+            warn!(slot, "slot was written but is not on the finalized fork");
+            let block_time = self.slot_clock.block_time(slot).unwrap_or_else(|| Utc::now().timestamp());
+            writer.write(orphaned_slot_point(point_time(block_time, slot, 0), slot));
+        }
+        for (slot, updates) in events.released {
+            self.untimed.entry(slot).or_default().extend(updates);
+        }
+        self.write_timed(registry, writer, checkpoint).await
+    }
+
+    /// Writes every waiting slot whose block time is recorded, and those
+    /// that waited too long with an extrapolated time, then queues the
+    /// checkpoint past them for saving once the sinks have it.
+    async fn write_timed(
+        &mut self,
+        registry: &FilterRegistry,
+        writer: &Sinks,
+        checkpoint: &CheckpointStore,
+    ) -> anyhow::Result<()> {
+        let latest = self.slot_clock.latest();
+        let ready: Vec<u64> = self
+            .untimed
+            .keys()
+            .copied()
+            .filter(|slot| {
+                self.slot_clock.recorded(*slot).is_some()
+                    || latest.map_or(false, |latest| latest > slot + MAX_BLOCK_TIME_WAIT_SLOTS)
+            })
+            .collect();
+        let Some(last) = ready.last().copied() else {
+            return Ok(());
+        };
+        let mut receipt = Receipt::default();
+        for slot in ready {
+            let updates = self.untimed.remove(&slot).unwrap_or_default();
+            let block_time = match self.slot_clock.recorded(slot) {
+                Some(block_time) => Some(block_time),
+                None => {
+                    warn!(slot, "no block meta for slot, writing it with an estimated time");
+                    metrics::ESTIMATED_BLOCK_TIMES.inc();
+                    self.slot_clock.block_time(slot)
+                }
+            };
+            for update in updates {
+                for filter in update.filters {
+                    let signature = update.signature.clone();
+                    self.released.filters.insert(filter, FilterCheckpoint { slot, signature });
+                }
+                for mut point in update.points {
+                    if !self.recent.insert(&point) {
+                        metrics::DUPLICATE_POINTS.inc();
+                        continue;
+                    }
+                    if let Some(block_time) = block_time {
+                        point.time = restamp(point.time, block_time);
+                    }
+                    receipt.merge(writer.write(point));
+                }
+            }
         }
+        self.advance(last, &registry.names());
+        self.unsettled.push_back((receipt, self.released.clone()));
+        self.save_settled(writer, checkpoint).await
+    }
+
+    /// Records `slot` as handed to the sinks, for every current filter
+    /// whether or not it matched anything.
+    fn advance(&mut self, slot: u64, filters: &[String]) {
+        if self.last_slot.map_or(true, |last| slot > last) {
+            self.last_slot = Some(slot);
+            self.released.slot = slot;
+            for name in filters {
+                self.released
+                    .filters
+                    .entry(name.clone())
+                    .or_insert(FilterCheckpoint { slot, signature: None })
+                    .slot = slot;
+            }
+            self.status.set_last_slot(slot, self.slot_clock.block_time(slot).unwrap_or(0));
+        }
+    }
+
+    /// Persists the newest released checkpoint every sink has settled, held
+    /// back to the start of any gap still being backfilled.
+    async fn save_settled(&mut self, writer: &Sinks, checkpoint: &CheckpointStore) -> anyhow::Result<()> {
+        let mut settled = None;
+        while let Some((receipt, _)) = self.unsettled.front() {
+            if !writer.settled(receipt) {
+                break;
+            }
+            settled = self.unsettled.pop_front().map(|(_, checkpoint)| checkpoint);
+        }
+        let Some(mut settled) = settled else {
+            return Ok(());
+        };
+        self.gaps.retain_mut(|gap| !gap.settled(writer));
+        // Slots still waiting for their block time were not written.
+        let floor = self
+            .gaps
+            .iter()
+            .map(|gap| gap.after)
+            .chain(self.untimed.keys().next().map(|untimed| untimed.saturating_sub(1)))
+            .min();
+        if let Some(floor) = floor {
+            settled.slot = settled.slot.min(floor);
+            for filter in settled.filters.values_mut() {
+                filter.slot = filter.slot.min(floor);
+            }
+        }
+        checkpoint.save(&settled).await
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let geyser_endpoint = std::env::var("GEYSER_ENDPOINT").expect("GEYSER_ENDPOINT must be set");
-    let x_token = std::env::var("X_TOKEN").expect("X_TOKEN must be set");
-    let influxdb_url = std::env::var("INFLUXDB_URL").unwrap_or_else(|_| "http://influxdb:8086".to_string());
-    let pubkey = std::env::var("PUBKEY").expect("PUBKEY must be set");
-    let client = GeyserGrpcClient::build_from_shared(geyser_endpoint)?.x_token(Some(x_token)).expect("oops").connect().await.expect("oops");
-
-
-    let influxdb_client = Client::new(influxdb_url, "mybucket");
-    let mut transactions: TransactionsFilterMap = HashMap::new();
-    transactions.insert(
-        "client".to_string(),
-        SubscribeRequestFilterTransactions {
-            vote: Some(false),
-            failed: Some(false),
-            signature: None,
-            account_include: vec![pubkey.clone()],
-            account_exclude: vec![],
-            account_required: vec![],
-        },
-    );
+async fn connect(
+    config: &GeyserConfig,
+) -> anyhow::Result<GeyserGrpcClient<impl yellowstone_grpc_proto::tonic::service::Interceptor>> {
+    let client = GeyserGrpcClient::build_from_shared(config.endpoint.clone())?
+        .x_token(config.x_token.as_ref().map(|token| token.expose().to_string()))?
+        .connect_timeout(config.connect_timeout)
+        .connect()
+        .await?;
+    Ok(client)
+}
 
-    let request = SubscribeRequest {
-        transactions: transactions.into(),
-        ..Default::default()
-    };
+/// Runs one subscription until the stream ends or fails. The caller owns the
+/// reconnect loop; `state` carries the resume point across connections.
+///
+/// The pinned yellowstone-grpc-proto has no `from_slot` on `SubscribeRequest`,
+/// so the server always resumes at the tip and the gap since `state.last_slot`
+/// is filled over RPC by a background backfill.
+///
+/// Filter changes are pushed to the server on the open stream; a new
+/// `SubscribeRequest` replaces the previous one without reconnecting.
+///
+/// Returns `Ok` without reconnecting once `shutdown` turns true, after
+/// finishing the message in hand and closing the request stream.
+///
+/// A replay passes a recording as `stream` and a sink that drops the
+/// requests, so recorded traffic takes exactly the live path. `recorder`
+/// saves every message before it is processed.
+///
+/// Pings go out every `ping_interval` so the server always has something to
+/// answer; a stream silent for `stall_timeout` despite that is treated as a
+/// half-open connection and dropped so the caller reconnects.
+async fn geyser_subscribe<Tx, Rx, E>(
+    mut subscribe_tx: Tx,
+    mut stream: Rx,
+    config: &GeyserConfig,
+    registry: &FilterRegistry,
+    state: &mut StreamState,
+    backoff: &mut Backoff,
+    checkpoint: &CheckpointStore,
+    backfill: &Arc<Backfill>,
+    writer: &Sinks,
+    mut recorder: Option<&mut Recorder>,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()>
+where
+    Tx: futures::Sink<SubscribeRequest> + Unpin,
+    Tx::Error: std::error::Error + Send + Sync + 'static,
+    Rx: futures::Stream<Item = Result<SubscribeUpdate, E>> + Unpin,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let mut filter_changes = registry.subscribe();
+    subscribe_tx.send(registry.request()).await?;
+    state.status.set_connected(true);
+
+    info!(
+        reconnects = state.reconnects,
+        resume_after = ?state.last_slot,
+        "stream opened"
+    );
+    if let Some(last_slot) = state.last_slot {
+        // Backfills still running cover older parts of the same gap; one
+        // backfill from the oldest start to the new tip replaces them, so
+        // flapping connections do not pile up backfills.
+        let mut after = state.resume_after.take().map_or(last_slot, |resume| resume.min(last_slot));
+        state.gaps.retain(|gap| match &gap.task {
+            Some(task) if !task.is_finished() => {
+                task.abort();
+                after = after.min(gap.after);
+                false
+            }
+            _ => true,
+        });
+        let task = spawn_backfill(backfill.clone(), after, None, writer.clone());
+        state.gaps.push(Gap {
+            after,
+            task: Some(task),
+            receipt: None,
+        });
+    }
 
-    let resub = 100; 
-    geyser_subscribe(client, request, resub, influxdb_client).await?;
+    let mut ping = tokio::time::interval(config.ping_interval);
+    ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut ping_id = 0;
+    let mut last_message = tokio::time::Instant::now();
+    loop {
+        let message = tokio::select! {
+            message = stream.next() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            changed = filter_changes.changed() => {
+                changed?;
+                info!("filters changed, updating subscription");
+                subscribe_tx.send(registry.request()).await?;
+                continue;
+            }
+            _ = ping.tick() => {
+                ping_id += 1;
+                subscribe_tx.send(ping_request(ping_id)).await?;
+                // Sinks may have caught up since the last release.
+                state.save_settled(writer, checkpoint).await?;
+                continue;
+            }
+            _ = shutdown.wait_for(|stop| *stop) => {
+                info!("closing stream");
+                subscribe_tx.close().await?;
+                return Ok(());
+            }
+            _ = tokio::time::sleep_until(last_message + config.stall_timeout) => {
+                anyhow::bail!("no message for {:?}, assuming the stream is dead", config.stall_timeout);
+            }
+        };
+        let msg = message?;
+        if let Some(recorder) = recorder.as_deref_mut() {
+            if let Err(error) = recorder.record(&msg).await {
+                warn!(?error, "failed to record message");
+            }
+        }
+        last_message = tokio::time::Instant::now();
+        state.status.set_last_message();
+        backoff.reset();
+        let update_type = update_type(&msg.update_oneof);
+        metrics::MESSAGES.with_label_values(&[update_type]).inc();
 
+        // Server keepalive; answering keeps load balancers from closing
+        // a stream that is otherwise quiet in one direction.
+        if let Some(UpdateOneof::Ping(_)) = msg.update_oneof {
+            ping_id += 1;
+            subscribe_tx.send(ping_request(ping_id)).await?;
+            continue;
+        }
+        let span = debug_span!(
+            "message",
+            update_type,
+            slot = field::Empty,
+            signature = field::Empty,
+            bonding = field::Empty,
+        );
+        handle_update(msg, registry, state, checkpoint, writer)
+            .instrument(span)
+            .await?;
+    }
     Ok(())
 }
 
-async fn geyser_subscribe(
-    mut client: GeyserGrpcClient<impl yellowstone_grpc_proto::tonic::service::Interceptor>,
-    request: SubscribeRequest,
-    resub: usize,
-    influxdb_client: Client,
+/// Routes one update to the decoder of the filters it matched and hands the
+/// resulting points to the commitment buffer.
+async fn handle_update(
+    msg: SubscribeUpdate,
+    registry: &FilterRegistry,
+    state: &mut StreamState,
+    checkpoint: &CheckpointStore,
+    writer: &Sinks,
 ) -> anyhow::Result<()> {
-    let (mut subscribe_tx, mut stream) = client.subscribe_with_request(Some(request)).await?;
-    let pubkey = std::env::var("PUBKEY").expect("PUBKEY must be set");
-    let rpc_client = solana_client::nonblocking::rpc_client::RpcClient::new_with_commitment(
-        "https://rpc.shyft.to?api_key=1y872euEMghE5flT".to_string(),
-        solana_sdk::commitment_config::CommitmentConfig::confirmed(),
-    );
+    let block_time = |clock: &SlotClock, slot| clock.block_time(slot).unwrap_or_else(|| Utc::now().timestamp());
+    let span = Span::current();
 
+    // Updates for filters removed since are dropped.
+    let decoder = registry.decoder_for(&msg.filters);
+    let filters: BTreeSet<String> = msg.filters.iter().map(|key| filter_name(key).to_string()).collect();
+    match msg.update_oneof {
+        Some(UpdateOneof::Account(SubscribeUpdateAccount { account: Some(account), slot, .. })) => {
+            span.record("slot", slot);
+            let pubkey = Pubkey::try_from(account.pubkey.as_slice())
+                .map_err(|_| anyhow::anyhow!("invalid account pubkey"))?;
+            span.record("bonding", field::display(&pubkey));
+            if decoder != Some(DecoderKind::Bonding) {
+                return Ok(());
+            }
+            let token_bonding = match TokenBondingV0::try_deserialize(&mut account.data.as_slice()) {
+                Ok(token_bonding) => token_bonding,
+                Err(error) => {
+                    metrics::DECODE_FAILURES.with_label_values(&["account"]).inc();
+                    warn!(?error, "failed to decode TokenBondingV0");
+                    return Ok(());
+                }
+            };
+            metrics::BONDING_ACCOUNTS_DECODED.inc();
+            debug!(write_version = account.write_version, "bonding account updated");
+
+            let time = point_time(block_time(&state.slot_clock, slot), slot, account.write_version);
+            let point = account_update_point(time, &pubkey, slot, account.write_version, &token_bonding);
+            let update = HeldUpdate {
+                filters,
+                signature: account.txn_signature.map(|signature| bs58::encode(signature).into_string()),
+                points: vec![point],
+            };
+            let events = state.slots.hold(slot, update);
+            state.apply(events, registry, writer, checkpoint).await?;
+        }
+        Some(UpdateOneof::Transaction(SubscribeUpdateTransaction { transaction: Some(tx), slot })) => {
+            span.record("slot", slot);
+            let signature = bs58::encode(&tx.signature).into_string();
+            span.record("signature", field::display(&signature));
+            let Some(decoder) = decoder else {
+                return Ok(());
+            };
+            let block_time = block_time(&state.slot_clock, slot);
+            let points = process_transaction(slot, tx, block_time, Source::Geyser, decoder)?;
+            let update = HeldUpdate {
+                filters,
+                signature: Some(signature),
+                points,
+            };
+            let events = state.slots.hold(slot, update);
+            state.apply(events, registry, writer, checkpoint).await?;
+        }
+        Some(UpdateOneof::Slot(SubscribeUpdateSlot { slot, parent, status, .. })) => {
+            span.record("slot", slot);
+            state.status.set_tip_slot(slot);
+            let Ok(status) = CommitmentLevel::try_from(status) else {
+                return Ok(());
+            };
+            let events = state.slots.update(slot, parent, status);
+            state.apply(events, registry, writer, checkpoint).await?;
+        }
+        Some(UpdateOneof::BlockMeta(SubscribeUpdateBlockMeta { slot, block_time: Some(block_time), .. })) => {
+            state.slot_clock.record(slot, block_time.timestamp);
+            state.write_timed(registry, writer, checkpoint).await?;
+        }
+        Some(UpdateOneof::Pong(_)) => {}
+        _ => debug!("ignoring update"),
+    }
+    Ok(())
+}
 
+/// `update_type` label of the messages metric.
+fn update_type(update: &Option<UpdateOneof>) -> &'static str {
+    match update {
+        Some(UpdateOneof::Account(_)) => "account",
+        Some(UpdateOneof::Transaction(_)) => "transaction",
+        Some(UpdateOneof::Slot(_)) => "slot",
+        Some(UpdateOneof::BlockMeta(_)) => "block_meta",
+        Some(UpdateOneof::Ping(_)) => "ping",
+        Some(UpdateOneof::Pong(_)) => "pong",
+        _ => "other",
+    }
+}
 
-    println!("stream opened");
-    let mut counter = 0;
-    while let Some(message) = stream.next().await {
-        match message {
-            Ok(msg) => {
-                match msg.update_oneof {
-
-                    Some(UpdateOneof::Transaction(tx)) => {
-                        //TransactionPretty { slot: 286155800, signature: 4HJadortBepQmuVYy9K8cy9Nkc54N16Aw8dde1qZtPVKmZbVtVEvUkZo317BVmjkuK7LjkRLGhMXSijy4wJj7adA, is_vote: false, tx: {"transaction":["AaQcNDBUQoU2fiuRMjo1IhNledDJ4jNxUsvjJ/iYkvznATcdvBvlRXJJkJMhzNdtkgzUMUplFST0rggqxGrS5AUBAAgPeQpnNG4nXfqyXl2/8SVo8hHthq6S6SahHQVoV2dVting+lJNsnVDNuKSPUZiUEAiYSSCEnMaHs68KALFo7TQaw3yhz2JDSLYllB/qFBeatBYeINsfF3AFY9eavzRHqvwMvY08+F6m6HsmVLYH9SOYig3xCbEOgzC690+iz1TXFPX5ALtD5SV22NTSmIZ/ZP5kbXB2E0rt1naikiL9tD+snURdlaeVu34uYFQ7pzzNtKeSJT2fOb4LVhCdDTKg8VyGSyJMxeJt/Vw9lbZVjFWEmVJH/UkDhOrmy8PE9pCDkIDBkZv5SEXMv/srbpyw5vnvIzlu8X3EmssQ5s6QAAAAAa1UfcDTtRKNcazhMaHWRZOMuxlFxdcIfzVvrIXVz0Zqwlb6uww+j6BPqki5YOSJCE4HDgFg06ew4mcSQTCyRIG3fbh12Whk9nL4UbO63msHLSF7V9bN5E6jPWFfv8AqQan1RcYx3TJKFZjmGkdXraLXrijm0ttXHNVWyEAAAAAirB6YPhh9DJLoFp4P5yU630paUWcpZu9gNfsedPWipUQouIYHEdvFEDB2N5tZhKGfh10G3s7f/PghvEiKcpI0QAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAALaPhFSPVgF7InUd8gy+9M8JOPDotf9C3bgfYZLgrvXECBwAJA1DDAAAAAAAACBABCQIDBAAFBQoLAAwCDQYOGqFR6t35418UAYCWmAAAAAAAILorAAAAAAAA","base64"],"meta":{"err":null,"status":{"Ok":null},"fee":15000,"preBalances":[272278265,4454400,1461600,1461600,2039280,2039280,1244279970071,1,1141440,4370880,934087680,1169280,2366400,0,1],"postBalances":[262263265,4454400,1461600,1461600,2039280,2039280,1244289970071,1,1141440,4370880,934087680,1169280,2366400,0,1],"innerInstructions":[{"index":1,"instructions":[{"programIdIndex":10,"accounts":[3,5,1],"data":"6K8LvD9bV6dd","stackHeight":2},{"programIdIndex":14,"accounts":[0,6],"data":"3Bxs4NN8M2Yn4TLb","stackHeight":2},{"programIdIndex":10,"accounts":[2,4,13],"data":"6YF7VVXZihvw","stackHeight":2}]}],"logMessages":["Program ComputeBudget111111111111111111111111111111 invoke [1]","Program ComputeBudget111111111111111111111111111111 success","Program TBondmkCYxaPCKG4CHYfVTcwQ8on31xnJrPzk8F8WsS invoke [1]","Program log: Instruction: BuyNativeV0","Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA invoke [2]","Program log: Instruction: MintTo","Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA consumed 4492 of 77202 compute units","Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA success","Program 11111111111111111111111111111111 invoke [2]","Program 11111111111111111111111111111111 success","Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA invoke [2]","Program log: Instruction: MintTo","Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA consumed 4538 of 64575 compute units","Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA success","Program TBondmkCYxaPCKG4CHYfVTcwQ8on31xnJrPzk8F8WsS consumed 145107 of 199850 compute units","Program TBondmkCYxaPCKG4CHYfVTcwQ8on31xnJrPzk8F8WsS success"],"preTokenBalances":[{"accountIndex":4,"mint":"wSo1i13zTR3wrdwvf5AMkQ8Xwa5X2X3XyUzqs5bvkBy","uiTokenAmount":{"uiAmount":13.868257116,"decimals":9,"amount":"13868257116","uiAmountString":"13.868257116"},"owner":"G9DiB75qGBeVJf1DojhrycLSUhnqmKjG4EYnYLEACk8a","programId":"TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"},{"accountIndex":5,"mint":"4Rw8dGapeo6dviezobiQzsbAidDhix1biibGEyaapWHQ","uiTokenAmount":{"uiAmount":21.567875496,"decimals":9,"amount":"21567875496","uiAmountString":"21.567875496"},"owner":"99VXriv7RXJSypeJDBQtGRsak1n5o2NBzbtMXhHW2RNG","programId":"TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"}],"postTokenBalances":[{"accountIndex":4,"mint":"wSo1i13zTR3wrdwvf5AMkQ8Xwa5X2X3XyUzqs5bvkBy","uiTokenAmount":{"uiAmount":13.878257116,"decimals":9,"amount":"13878257116","uiAmountString":"13.878257116"},"owner":"G9DiB75qGBeVJf1DojhrycLSUhnqmKjG4EYnYLEACk8a","programId":"TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"},{"accountIndex":5,"mint":"4Rw8dGapeo6dviezobiQzsbAidDhix1biibGEyaapWHQ","uiTokenAmount":{"uiAmount":21.570770138,"decimals":9,"amount":"21570770138","uiAmountString":"21.570770138"},"owner":"99VXriv7RXJSypeJDBQtGRsak1n5o2NBzbtMXhHW2RNG","programId":"TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"}],"rewards":[],"loadedAddresses":{"writable":[],"readonly":[]},"computeUnitsConsumed":145257},"version":"legacy"} }
-
-
-            let decoded_transaction = tx.transaction.clone().unwrap().transaction.unwrap();
-            if let Some(meta) = &tx.transaction.unwrap().meta {
-                for (i, account_key) in decoded_transaction.message.unwrap().account_keys.iter().enumerate() {
-                    let pubkey = Pubkey::new(account_key.as_slice());
-    let account_info = rpc_client.get_account(&pubkey).await?;
-   
-    if let Ok(token_bonding) = TokenBondingV0::try_deserialize(&mut account_info.data.as_slice()) {
-                        
-                        println!("Account Info: {:?}", account_info);
-                        let account_update = AccountUpdate {
-                            time: Utc::now().into(),
-                            base_mint: token_bonding.base_mint.to_string(),
-                            target_mint: token_bonding.target_mint.to_string(),
-                            general_authority: token_bonding.general_authority.map(|auth| auth.to_string()),
-                            reserve_authority: token_bonding.reserve_authority.map(|auth| auth.to_string()),
-                            curve_authority: token_bonding.curve_authority.map(|auth| auth.to_string()),
-                            base_storage: token_bonding.base_storage.to_string(),
-                            buy_base_royalties: token_bonding.buy_base_royalties.to_string(),
-                            buy_target_royalties: token_bonding.buy_target_royalties.to_string(),
-                            sell_base_royalties: token_bonding.sell_base_royalties.to_string(),
-                            sell_target_royalties: token_bonding.sell_target_royalties.to_string(),
-                            buy_base_royalty_percentage: token_bonding.buy_base_royalty_percentage,
-                            buy_target_royalty_percentage: token_bonding.buy_target_royalty_percentage,
-                            sell_base_royalty_percentage: token_bonding.sell_base_royalty_percentage,
-                            sell_target_royalty_percentage: token_bonding.sell_target_royalty_percentage,
-                            curve: token_bonding.curve.to_string(),
-                            mint_cap: token_bonding.mint_cap,
-                            purchase_cap: token_bonding.purchase_cap,
-                            go_live_unix_time: token_bonding.go_live_unix_time,
-                            freeze_buy_unix_time: token_bonding.freeze_buy_unix_time,
-                            created_at_unix_time: token_bonding.created_at_unix_time,
-                            buy_frozen: token_bonding.buy_frozen,
-                            sell_frozen: token_bonding.sell_frozen,
-                            index: token_bonding.index,
-                            bump_seed: token_bonding.bump_seed,
-                            base_storage_bump_seed: token_bonding.base_storage_bump_seed,
-                            target_mint_authority_bump_seed: token_bonding.target_mint_authority_bump_seed,
-                            base_storage_authority_bump_seed: token_bonding.base_storage_authority_bump_seed,
-                            reserve_balance_from_bonding: token_bonding.reserve_balance_from_bonding,
-                            supply_from_bonding: token_bonding.supply_from_bonding,
-                            ignore_external_reserve_changes: token_bonding.ignore_external_reserve_changes,
-                            ignore_external_supply_changes: token_bonding.ignore_external_supply_changes,
-                        };
-
-                        let write_query = account_update.into_query("account_updates");
-                        println!("Executing query: {:?}", write_query);
-                        influxdb_client.query(write_query).await?;
-                }
-            }
+/// A request carrying only a ping; the server answers with a pong and keeps
+/// the current filters.
+fn ping_request(id: i32) -> SubscribeRequest {
+    SubscribeRequest {
+        ping: Some(SubscribeRequestPing { id }),
+        ..Default::default()
     }
-                        continue;
+}
+
+/// Backfills everything after `after` up to `until`, or the current tip,
+/// without holding up the live stream. Failures are retried with backoff
+/// until the backfill succeeds or the task is aborted; the task yields the
+/// receipt of the backfilled points.
+fn spawn_backfill(
+    backfill: Arc<Backfill>,
+    after: u64,
+    until: Option<u64>,
+    writer: Sinks,
+) -> JoinHandle<Receipt> {
+    let span = info_span!("backfill", after, until = ?until);
+    let task = async move {
+        let mut backoff = Backoff::new(BACKFILL_RETRY_MIN, BACKFILL_RETRY_MAX);
+        // Pinned once known, so retries do not chase the tip.
+        let mut until = until;
+        loop {
+            let tip = match until {
+                Some(until) => Ok(until),
+                None => backfill.tip().await,
+            };
+            let result = match tip {
+                Ok(tip) => {
+                    until = Some(tip);
+                    if tip > after {
+                        backfill.run(after, tip, &writer).await
+                    } else {
+                        Ok((0, Receipt::default()))
                     }
-                    _ => {}
                 }
-                println!("new message: {msg:?}")
-            }
-            Err(error) => {
-                println!("error: {error:?}");
-                break;
+                Err(error) => Err(error),
+            };
+            match result {
+                Ok((count, receipt)) => {
+                    info!(transactions = count, retries = backoff.attempt(), "backfill done");
+                    return receipt;
+                }
+                Err(error) => {
+                    let delay = backoff.next_delay();
+                    error!(?error, ?delay, attempt = backoff.attempt(), "backfill failed, retrying");
+                    tokio::time::sleep(delay).await;
+                }
             }
         }
+    };
+    tokio::spawn(task.instrument(span))
+}
+
+/// Where a transaction came from; written as a tag so dashboards can tell
+/// backfilled points apart from live ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Geyser,
+    Backfill,
+}
+
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Geyser => "geyser",
+            Source::Backfill => "backfill",
+        }
+    }
+}
 
-        // Example to illustrate how to resubscribe/update the subscription
-        counter += 1;
+/// Shared decode path for bonding program transactions, live or backfilled.
+/// The caller decides when the points are written.
+#[tracing::instrument(level = "debug", skip(tx, block_time, source), fields(source = source.as_str()))]
+pub fn process_transaction(
+    slot: u64,
+    tx: SubscribeUpdateTransactionInfo,
+    block_time: i64,
+    source: Source,
+    decoder: DecoderKind,
+) -> anyhow::Result<Vec<Point>> {
+    let (fee, compute_units_consumed) = tx
+        .meta
+        .as_ref()
+        .map_or((0, None), |meta| (meta.fee, meta.compute_units_consumed));
+    let signature = bs58::encode(&tx.signature).into_string();
+    let time = point_time(block_time, slot, transaction_sequence(tx.index, 0));
+    let mut points = vec![transaction_point(time, source, &signature, slot, fee, compute_units_consumed)];
+    if decoder == DecoderKind::Raw {
+        return Ok(points);
     }
-    println!("stream closed");
-    Ok(())
-}
\ No newline at end of file
+
+    let decoded = match decode_transaction(&tx) {
+        Ok(decoded) => decoded,
+        Err(error) => {
+            metrics::DECODE_FAILURES.with_label_values(&["transaction"]).inc();
+            warn!(%signature, ?error, "failed to decode transaction");
+            return Ok(points);
+        }
+    };
+    // Ordinal 0 is the transaction point itself.
+    let ix_time = |ordinal: usize| point_time(block_time, slot, transaction_sequence(tx.index, ordinal + 1));
+    for (ordinal, ix) in decoded.iter().enumerate() {
+        debug!(
+            %signature,
+            bonding = ?ix.event.token_bonding(),
+            event = ix.event.event_type(),
+            "decoded instruction"
+        );
+        points.push(event_point(slot, &signature, ix, ix_time(ordinal), source));
+    }
+
+    let (Some(message), Some(meta)) = (
+        tx.transaction.as_ref().and_then(|transaction| transaction.message.as_ref()),
+        tx.meta.as_ref(),
+    ) else {
+        return Ok(points);
+    };
+    let keys = account_keys(&message.account_keys, Some(meta))?;
+    for (ordinal, ix) in decoded.iter().enumerate() {
+        let Some(trade) = compute_trade(ix, &keys, meta) else {
+            continue;
+        };
+        points.push(trade_point(
+            ix_time(ordinal),
+            source,
+            &signature,
+            slot,
+            ix.instruction_index,
+            ix.inner_index,
+            &trade,
+        ));
+    }
+    Ok(points)
+}
+
@@ -0,0 +1,212 @@
+//! Prometheus metrics, served in the text exposition format on
+//! `GET /metrics` at `metrics.listen`.
+//!
+//! Counters and histograms are updated where the work happens; gauges that
+//! mirror existing state (stream progress, sink queues) are read when
+//! scraped.
+
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tracing::{error, info};
+
+use crate::status::StreamStatus;
+use crate::writer::Sinks;
+
+static REGISTRY: Lazy<Registry> =
+    Lazy::new(|| Registry::new_custom(Some("consumer".to_string()), None).expect("valid prefix"));
+
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: M) -> M {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric registered once");
+    metric
+}
+
+/// Geyser messages by `update_type`.
+pub static MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("messages_total", "Geyser messages received"),
+            &["update_type"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static BONDING_ACCOUNTS_DECODED: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new("bonding_accounts_decoded_total", "TokenBondingV0 accounts decoded").unwrap())
+});
+
+/// Accounts or transactions that could not be decoded, by `kind`.
+pub static DECODE_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("decode_failures_total", "Updates that failed to decode"),
+            &["kind"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Solana JSON-RPC requests by `method` and `result` (`ok` or `error`).
+pub static RPC_CALLS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("rpc_calls_total", "Solana JSON-RPC requests"),
+            &["method", "result"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Duration of a single `Sink::write`, retries counted separately.
+pub static SINK_WRITE_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new("sink_write_seconds", "Time spent in one sink write"),
+            &["sink"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Write failures by `sink` and `kind` (`retryable` or `fatal`).
+pub static SINK_WRITE_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("sink_write_errors_total", "Failed sink writes"),
+            &["sink", "kind"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static SINK_BATCH_SIZE: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new("sink_batch_size", "Points per sink write")
+                .buckets(exponential_buckets(1.0, 4.0, 8).unwrap()),
+            &["sink"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Points of the geyser path dropped as already written.
+pub static DUPLICATE_POINTS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new("duplicate_points_total", "Points dropped as duplicates").unwrap())
+});
+
+/// Slots written with an extrapolated time because their block meta never
+/// arrived; their points may not line up with backfilled copies.
+pub static ESTIMATED_BLOCK_TIMES: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new("estimated_block_times_total", "Slots written without a block time").unwrap())
+});
+
+pub static RECONNECTS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new("reconnects_total", "Geyser stream reconnects").unwrap())
+});
+
+static LAST_SLOT: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("last_slot", "Highest slot handed to the sinks").unwrap())
+});
+
+static TIP_SLOT: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("tip_slot", "Highest slot announced by the server").unwrap())
+});
+
+static SLOTS_BEHIND: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("slots_behind", "Slots between the tip and last_slot").unwrap())
+});
+
+static LAG_SECONDS: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("lag_seconds", "Seconds since the block time of last_slot").unwrap())
+});
+
+static SECONDS_SINCE_LAST_MESSAGE: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("seconds_since_last_message", "Seconds since the last geyser message").unwrap())
+});
+
+static CONNECTED: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("connected", "1 while the geyser stream is open").unwrap())
+});
+
+static SINK_QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new("sink_queue_depth", "Points waiting in the sink's channel"),
+            &["sink"],
+        )
+        .unwrap(),
+    )
+});
+
+static SINK_SPOOLED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new("sink_spooled_points", "Points waiting in the sink's spool file"),
+            &["sink"],
+        )
+        .unwrap(),
+    )
+});
+
+#[derive(Clone)]
+struct MetricsState {
+    sinks: Sinks,
+    status: Arc<StreamStatus>,
+}
+
+pub async fn serve(listen: SocketAddr, sinks: Sinks, status: Arc<StreamStatus>) -> anyhow::Result<()> {
+    // Metrics are registered on first use; force them so every series is
+    // exported from the first scrape on.
+    Lazy::force(&MESSAGES);
+    Lazy::force(&BONDING_ACCOUNTS_DECODED);
+    Lazy::force(&DECODE_FAILURES);
+    Lazy::force(&RPC_CALLS);
+    Lazy::force(&SINK_WRITE_SECONDS);
+    Lazy::force(&SINK_WRITE_ERRORS);
+    Lazy::force(&SINK_BATCH_SIZE);
+    Lazy::force(&DUPLICATE_POINTS);
+    Lazy::force(&ESTIMATED_BLOCK_TIMES);
+    Lazy::force(&RECONNECTS);
+
+    let app = Router::new()
+        .route("/metrics", get(scrape))
+        .with_state(MetricsState { sinks, status });
+
+    info!(%listen, "metrics endpoint listening");
+    axum::Server::bind(&listen)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
+
+async fn scrape(State(state): State<MetricsState>) -> impl IntoResponse {
+    let snapshot = state.status.snapshot();
+    LAST_SLOT.set(snapshot.last_slot.unwrap_or(0) as i64);
+    TIP_SLOT.set(snapshot.tip_slot.unwrap_or(0) as i64);
+    SLOTS_BEHIND.set(snapshot.slots_behind.unwrap_or(0) as i64);
+    LAG_SECONDS.set(snapshot.lag_seconds.unwrap_or(0));
+    SECONDS_SINCE_LAST_MESSAGE.set(snapshot.seconds_since_last_message.unwrap_or(0.0));
+    CONNECTED.set(snapshot.connected.into());
+    for writer in state.sinks.writers() {
+        SINK_QUEUE_DEPTH
+            .with_label_values(&[writer.name()])
+            .set(writer.queue_depth() as i64);
+        SINK_SPOOLED
+            .with_label_values(&[writer.name()])
+            .set(writer.spooled() as i64);
+    }
+
+    let mut body = Vec::new();
+    if let Err(error) = TextEncoder::new().encode(&REGISTRY.gather(), &mut body) {
+        error!(?error, "failed to encode metrics");
+    }
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body)
+}
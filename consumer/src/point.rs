@@ -0,0 +1,117 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A single field value. Serialized untagged so JSON sinks see plain values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Bool(bool),
+    UnsignedInteger(u64),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl From<bool> for FieldValue {
+    fn from(value: bool) -> Self {
+        FieldValue::Bool(value)
+    }
+}
+
+impl From<u8> for FieldValue {
+    fn from(value: u8) -> Self {
+        FieldValue::UnsignedInteger(value.into())
+    }
+}
+
+impl From<u16> for FieldValue {
+    fn from(value: u16) -> Self {
+        FieldValue::UnsignedInteger(value.into())
+    }
+}
+
+impl From<u32> for FieldValue {
+    fn from(value: u32) -> Self {
+        FieldValue::UnsignedInteger(value.into())
+    }
+}
+
+impl From<u64> for FieldValue {
+    fn from(value: u64) -> Self {
+        FieldValue::UnsignedInteger(value)
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(value: i64) -> Self {
+        FieldValue::Integer(value)
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        FieldValue::Float(value)
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        FieldValue::Text(value)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        FieldValue::Text(value.to_string())
+    }
+}
+
+/// Sink-agnostic output of the pipeline: a measurement with indexed tags and
+/// free-form fields, the shape every sink maps from.
+///
+/// `id` is deterministic, see the builders in [`crate::schema`]: seeing the
+/// same event again yields the same `(measurement, id)`, which sinks use to
+/// upsert or drop duplicates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub measurement: String,
+    pub id: String,
+    pub time: DateTime<Utc>,
+    pub tags: BTreeMap<String, String>,
+    pub fields: BTreeMap<String, FieldValue>,
+}
+
+impl Point {
+    pub fn new(measurement: &str, id: impl Into<String>, time: DateTime<Utc>) -> Self {
+        Self {
+            measurement: measurement.to_string(),
+            id: id.into(),
+            time,
+            tags: BTreeMap::new(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    pub fn tag(mut self, key: &str, value: impl ToString) -> Self {
+        self.tags.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn field(mut self, key: &str, value: impl Into<FieldValue>) -> Self {
+        self.fields.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Adds the field only when `value` is set.
+    pub fn field_opt<T: Into<FieldValue>>(self, key: &str, value: Option<T>) -> Self {
+        match value {
+            Some(value) => self.field(key, value),
+            None => self,
+        }
+    }
+
+    pub fn tag_value(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+}
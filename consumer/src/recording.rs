@@ -0,0 +1,452 @@
+//! Raw geyser traffic on disk, for testing decoding changes offline.
+//!
+//! A recording is a directory of files, each a sequence of length-delimited
+//! [`Recorded`] protobufs: the received `SubscribeUpdate` plus its receive
+//! time. Files are named after the time they were opened, so sorting
+//! them by name orders the whole recording.
+
+use anyhow::{bail, Context};
+use chrono::Utc;
+use futures::stream::{self, Stream};
+use prost::Message;
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    time::Instant,
+};
+use tracing::{info, warn};
+use yellowstone_grpc_proto::prelude::SubscribeUpdate;
+
+const EXTENSION: &str = "pb";
+
+/// Recording is off unless `dir` is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordConfig {
+    pub dir: Option<PathBuf>,
+    /// A new file is started once the current one reaches this size.
+    pub rotate_mb: u64,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            rotate_mb: 256,
+        }
+    }
+}
+
+/// Set from the command line only: feed a recording through the stream
+/// processing instead of connecting to geyser.
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// A recording directory or a single file of one.
+    pub path: PathBuf,
+    /// 1 replays at the recorded pace, 10 ten times faster, 0 as fast as the
+    /// sinks accept.
+    pub speed: f64,
+}
+
+/// One recorded message. `update` is re-encoded from the decoded message, so
+/// fields unknown to this version of the protobufs are not kept.
+#[derive(Clone, PartialEq, Message)]
+pub struct Recorded {
+    /// Receive time in microseconds since the Unix epoch.
+    #[prost(int64, tag = "1")]
+    pub received_at_micros: i64,
+    #[prost(message, optional, tag = "2")]
+    pub update: Option<SubscribeUpdate>,
+}
+
+/// Appends every message to the current file of the recording, starting a
+/// new one when it grows past `rotate_mb`.
+pub struct Recorder {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    file: Option<(BufWriter<File>, u64)>,
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf, config: &RecordConfig) -> Self {
+        Self {
+            dir,
+            max_file_bytes: config.rotate_mb.saturating_mul(1024 * 1024),
+            file: None,
+        }
+    }
+
+    /// Records are buffered, so a crash loses the unflushed tail of the
+    /// current file; replay skips a record cut short as truncated.
+    pub async fn record(&mut self, update: &SubscribeUpdate) -> anyhow::Result<()> {
+        let record = Recorded {
+            received_at_micros: Utc::now().timestamp_micros(),
+            update: Some(update.clone()),
+        };
+        let bytes = record.encode_length_delimited_to_vec();
+        if self.file.as_ref().map_or(true, |(_, written)| *written >= self.max_file_bytes) {
+            self.rotate().await?;
+        }
+        let (file, written) = self.file.as_mut().expect("opened above");
+        file.write_all(&bytes).await.context("failed to write recording")?;
+        *written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Flushes and syncs the current file. Call before dropping the
+    /// recorder, which would discard what is still buffered.
+    pub async fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some((mut file, _)) = self.file.take() {
+            file.flush().await.context("failed to flush recording")?;
+            file.get_ref().sync_all().await.context("failed to sync recording")?;
+        }
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> anyhow::Result<()> {
+        self.finish().await?;
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let name = format!("geyser-{}.{EXTENSION}", Utc::now().format("%Y%m%dT%H%M%S%.3fZ"));
+        let path = self.dir.join(name);
+        let file = File::create(&path)
+            .await
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        info!(path = %path.display(), "recording to new file");
+        self.file = Some((BufWriter::new(file), 0));
+        Ok(())
+    }
+}
+
+/// The updates of a recording in order, paced by `speed`. Pauses that would
+/// last longer than `max_gap` once scaled, e.g. reconnects while recording or
+/// any quiet spell at speeds below 1, are shortened to it so the replay never
+/// trips the stall timeout.
+pub async fn replay(
+    path: &Path,
+    speed: f64,
+    max_gap: Duration,
+) -> anyhow::Result<impl Stream<Item = io::Result<SubscribeUpdate>>> {
+    let files = recording_files(path).await?;
+    info!(path = %path.display(), files = files.len(), speed, "replaying recording");
+    let reader = Replay {
+        files,
+        current: None,
+        speed,
+        max_gap,
+        previous: None,
+    };
+    Ok(stream::unfold(reader, |mut reader| async move {
+        match reader.next().await {
+            Ok(Some(update)) => Some((Ok(update), reader)),
+            Ok(None) => None,
+            Err(error) => Some((Err(error), reader)),
+        }
+    }))
+}
+
+async fn recording_files(path: &Path) -> anyhow::Result<VecDeque<PathBuf>> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    if metadata.is_file() {
+        return Ok(VecDeque::from([path.to_path_buf()]));
+    }
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(path)
+        .await
+        .with_context(|| format!("failed to list {}", path.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file = entry.path();
+        if file.extension().map_or(false, |extension| extension == EXTENSION) {
+            files.push(file);
+        }
+    }
+    if files.is_empty() {
+        bail!("no .{EXTENSION} files in {}", path.display());
+    }
+    files.sort();
+    Ok(files.into())
+}
+
+struct Replay {
+    files: VecDeque<PathBuf>,
+    current: Option<BufReader<File>>,
+    speed: f64,
+    max_gap: Duration,
+    /// Receive time of the previous record and when it was replayed.
+    previous: Option<(i64, Instant)>,
+}
+
+impl Replay {
+    async fn next(&mut self) -> io::Result<Option<SubscribeUpdate>> {
+        loop {
+            let Some(reader) = self.current.as_mut() else {
+                let Some(path) = self.files.pop_front() else {
+                    return Ok(None);
+                };
+                info!(path = %path.display(), "replaying file");
+                self.current = Some(BufReader::new(File::open(&path).await?));
+                continue;
+            };
+            match read_record(reader).await {
+                Ok(Some(record)) => {
+                    self.pace(record.received_at_micros).await;
+                    match record.update {
+                        Some(update) => return Ok(Some(update)),
+                        None => continue,
+                    }
+                }
+                Ok(None) => self.current = None,
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => {
+                    warn!("recording file ends in a truncated record, skipping it");
+                    self.current = None;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    async fn pace(&mut self, received_at_micros: i64) {
+        let now = Instant::now();
+        let deadline = match self.previous {
+            Some((previous, replayed)) if self.speed > 0.0 => {
+                let gap = Duration::from_micros(received_at_micros.saturating_sub(previous).max(0) as u64);
+                // Capped in seconds, as tiny speeds would overflow a Duration.
+                let scaled = (gap.as_secs_f64() / self.speed).min(self.max_gap.as_secs_f64());
+                replayed + Duration::from_secs_f64(scaled)
+            }
+            _ => now,
+        };
+        tokio::time::sleep_until(deadline).await;
+        self.previous = Some((received_at_micros, deadline.max(now)));
+    }
+}
+
+/// `None` at a clean end of file.
+async fn read_record(reader: &mut BufReader<File>) -> io::Result<Option<Recorded>> {
+    let Some(len) = read_length(reader).await? else {
+        return Ok(None);
+    };
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes).await?;
+    Recorded::decode(bytes.as_slice())
+        .map(Some)
+        .map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+/// Reads the varint length prefix written by `encode_length_delimited`.
+async fn read_length(reader: &mut BufReader<File>) -> io::Result<Option<usize>> {
+    let mut len: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = match reader.read_u8().await {
+            Ok(byte) => byte,
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof && shift == 0 => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        len |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(Some(len as usize));
+        }
+    }
+    Err(io::Error::new(ErrorKind::InvalidData, "invalid record length"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::hash::hash;
+    use futures::TryStreamExt;
+    use solana_sdk::pubkey::Pubkey;
+    use yellowstone_grpc_proto::prelude::{
+        subscribe_update::UpdateOneof, CommitmentLevel, CompiledInstruction, Message, SubscribeUpdatePing,
+        SubscribeUpdateSlot, SubscribeUpdateTransaction, SubscribeUpdateTransactionInfo, TokenBalance,
+        Transaction, TransactionStatusMeta, UiTokenAmount,
+    };
+
+    use crate::decoder::program_id;
+    use crate::filters::DecoderKind;
+    use crate::point::FieldValue;
+    use crate::Source;
+
+    /// Three slot updates and a truncated fourth, as left by a crash.
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/recording");
+
+    fn slot_update(slot: u64, status: CommitmentLevel) -> SubscribeUpdate {
+        SubscribeUpdate {
+            filters: vec!["bonding".to_string()],
+            update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+                slot,
+                parent: slot.checked_sub(1),
+                status: status as i32,
+            })),
+        }
+    }
+
+    async fn replay_all(path: &Path) -> Vec<SubscribeUpdate> {
+        replay(path, 0.0, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_the_fixture_and_skips_its_truncated_record() {
+        let updates = replay_all(Path::new(FIXTURE)).await;
+        assert_eq!(
+            updates,
+            vec![
+                slot_update(1000, CommitmentLevel::Processed),
+                slot_update(1001, CommitmentLevel::Processed),
+                slot_update(1000, CommitmentLevel::Confirmed),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_what_was_recorded_across_files() {
+        let dir = std::env::temp_dir().join(format!("recording-test-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        // Rotates after every record.
+        let mut recorder = Recorder::new(dir.clone(), &RecordConfig { dir: None, rotate_mb: 0 });
+        let recorded = vec![
+            slot_update(7, CommitmentLevel::Processed),
+            SubscribeUpdate {
+                filters: vec![],
+                update_oneof: Some(UpdateOneof::Ping(SubscribeUpdatePing {})),
+            },
+            slot_update(7, CommitmentLevel::Finalized),
+        ];
+        for update in &recorded {
+            recorder.record(update).await.unwrap();
+            // File names have millisecond resolution.
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        recorder.finish().await.unwrap();
+
+        assert_eq!(recording_files(&dir).await.unwrap().len(), 3);
+        assert_eq!(replay_all(&dir).await, recorded);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_replays_never_pause_longer_than_max_gap() {
+        let mut replay = Replay {
+            files: VecDeque::new(),
+            current: None,
+            speed: 0.1,
+            max_gap: Duration::from_secs(5),
+            previous: None,
+        };
+        replay.pace(0).await;
+        let start = Instant::now();
+        // One second recorded is ten at this speed.
+        replay.pace(1_000_000).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    /// A sell of 1000 target tokens for 1000 base tokens, laid out like the
+    /// decoder's sample: account `i` is `[i; 32]` and the program comes last.
+    fn sell_transaction(slot: u64) -> SubscribeUpdate {
+        let mut keys: Vec<Pubkey> = (0..16).map(|i| Pubkey::new_from_array([i; 32])).collect();
+        keys.push(program_id());
+        let mut data = hash(b"global:sell_v1").to_bytes()[..8].to_vec();
+        data.extend(1_000u64.to_le_bytes());
+        data.extend(5u64.to_le_bytes());
+        let balance = |account_index: u32, mint: &Pubkey, amount: u64| TokenBalance {
+            account_index,
+            mint: mint.to_string(),
+            ui_token_amount: Some(UiTokenAmount {
+                amount: amount.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let (base_mint, target_mint) = (keys[2], keys[3]);
+        SubscribeUpdate {
+            filters: vec!["bonding".to_string()],
+            update_oneof: Some(UpdateOneof::Transaction(SubscribeUpdateTransaction {
+                slot,
+                transaction: Some(SubscribeUpdateTransactionInfo {
+                    signature: vec![7; 64],
+                    index: 3,
+                    transaction: Some(Transaction {
+                        message: Some(Message {
+                            account_keys: keys.iter().map(|key| key.to_bytes().to_vec()).collect(),
+                            instructions: vec![CompiledInstruction {
+                                program_id_index: 16,
+                                accounts: (0..16).collect(),
+                                data,
+                            }],
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    meta: Some(TransactionStatusMeta {
+                        fee: 5_000,
+                        // Base storage pays out, the trader's target account burns.
+                        pre_token_balances: vec![balance(4, &base_mint, 10_000), balance(6, &target_mint, 1_000)],
+                        post_token_balances: vec![balance(4, &base_mint, 9_000), balance(6, &target_mint, 0)],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+            })),
+        }
+    }
+
+    #[tokio::test]
+    async fn replayed_bonding_transactions_produce_their_points() {
+        let dir = std::env::temp_dir().join(format!("recording-traffic-test-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let mut recorder = Recorder::new(dir.clone(), &RecordConfig { dir: None, rotate_mb: 1 });
+        for update in [sell_transaction(42), slot_update(42, CommitmentLevel::Confirmed)] {
+            recorder.record(&update).await.unwrap();
+        }
+        recorder.finish().await.unwrap();
+
+        let mut points = Vec::new();
+        for update in replay_all(&dir).await {
+            if let Some(UpdateOneof::Transaction(SubscribeUpdateTransaction { slot, transaction: Some(info) })) =
+                update.update_oneof
+            {
+                points.extend(
+                    crate::process_transaction(slot, info, 1_700_000_000, Source::Geyser, DecoderKind::Bonding)
+                        .unwrap(),
+                );
+            }
+        }
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        let measurements: Vec<&str> = points.iter().map(|point| point.measurement.as_str()).collect();
+        assert_eq!(measurements, ["bonding_transactions", "bonding_events", "bonding_trades"]);
+        let bonding = Pubkey::new_from_array([0; 32]).to_string();
+
+        let event = &points[1];
+        assert_eq!(event.tag_value("event_type"), Some("sell"));
+        assert_eq!(event.tag_value("pubkey"), Some(bonding.as_str()));
+        assert_eq!(event.fields["slot"], FieldValue::from(42u64));
+        assert_eq!(event.fields["target_amount"], FieldValue::from(1_000u64));
+
+        let trade = &points[2];
+        assert_eq!(trade.tag_value("side"), Some("sell"));
+        assert_eq!(trade.tag_value("pubkey"), Some(bonding.as_str()));
+        assert_eq!(trade.fields["reserve_change_raw"], FieldValue::from(-1_000i64));
+        assert_eq!(trade.fields["supply_change_raw"], FieldValue::from(-1_000i64));
+        assert_eq!(trade.fields["price"], FieldValue::from(1.0));
+        // An instruction's event and trade share its time, after the
+        // transaction's.
+        assert!(points[0].time < event.time);
+        assert_eq!(event.time, trade.time);
+    }
+}
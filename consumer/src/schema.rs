@@ -0,0 +1,329 @@
+//! Point schema written by the consumer. Every sink stores the same points
+//! (measurement, tags, fields); the API reads them back from InfluxDB and
+//! `api/src/schema.rs` mirrors the constants below, so bump them together.
+//!
+//! Every point is tagged with `schema_version`. Bonding points are tagged with
+//! the bonding account (`pubkey`) and, where known, `base_mint`/`target_mint`;
+//! `slot`, `source` and the point `id` are fields. Timestamps come from the
+//! block time, see [`point_time`].
+//!
+//! A live and a backfilled copy of the same event share ID, series and
+//! timestamp, so InfluxDB overwrites rather than duplicates it and Postgres
+//! upserts it: live points are only written once their slot's block meta has
+//! arrived, backfilled ones carry the same block time from RPC, and the
+//! sub-second part comes from the position in the block. A slot whose block
+//! meta never arrives is written with an extrapolated time after a while and
+//! counted in `estimated_block_times_total`; only its points can be stored
+//! twice.
+
+use chrono::{DateTime, TimeZone, Utc};
+use solana_sdk::pubkey::Pubkey;
+use spl_token_bonding::state::TokenBondingV0;
+
+use crate::decoder::{BondingEvent, BuyAmount, DecodedInstruction};
+use crate::point::Point;
+use crate::trades::Trade;
+use crate::Source;
+
+/// Version 1 was the untagged `account_updates` layout with wall-clock times,
+/// version 2 had neither point IDs nor the `source` tag.
+pub const SCHEMA_VERSION: &str = "3";
+
+pub const ACCOUNT_UPDATES: &str = "account_updates";
+pub const BONDING_TRANSACTIONS: &str = "bonding_transactions";
+pub const BONDING_EVENTS: &str = "bonding_events";
+pub const BONDING_TRADES: &str = "bonding_trades";
+/// Slots whose points were written and later turned out to be on a dead fork.
+pub const ORPHANED_SLOTS: &str = "orphaned_slots";
+
+/// Block times only have second precision and several slots share a second,
+/// so points would overwrite each other in InfluxDB (same series, same time).
+/// The sub-second part is therefore synthesized: tens of milliseconds from
+/// `slot % 100`, unique among the few slots of one second, and the remaining
+/// nanoseconds from a per-slot `sequence` below 10_000_000.
+pub fn point_time(block_time: i64, slot: u64, sequence: u64) -> DateTime<Utc> {
+    let nanos = (slot % 100) as u32 * 10_000_000 + (sequence % 10_000_000) as u32;
+    Utc.timestamp_opt(block_time, nanos).single().unwrap_or_default()
+}
+
+/// Sequence for points of one transaction: its index in the block, which
+/// geyser reports and backfill looks up, and the point's `ordinal` within
+/// the transaction. Unique per slot for blocks of up to 100_000 transactions
+/// with at most 100 points each.
+pub fn transaction_sequence(index: u64, ordinal: usize) -> u64 {
+    index.min(99_999) * 100 + (ordinal as u64).min(99)
+}
+
+/// ID of an `account_updates` point: one per account write.
+pub fn account_update_id(pubkey: &Pubkey, slot: u64, write_version: u64) -> String {
+    format!("{pubkey}:{slot}:{write_version}")
+}
+
+/// ID of the points of one instruction: the outer index, plus the position
+/// among its CPIs for inner instructions.
+pub fn instruction_id(signature: &str, instruction_index: u32, inner_index: Option<u32>) -> String {
+    match inner_index {
+        Some(inner_index) => format!("{signature}:{instruction_index}.{inner_index}"),
+        None => format!("{signature}:{instruction_index}"),
+    }
+}
+
+/// Moves `time` to `block_time`, keeping the synthesized sub-second part.
+/// Points are stamped when they arrive, often before their block time is
+/// known; restamping once it is gives live points the same time as their
+/// backfilled copies.
+pub fn restamp(time: DateTime<Utc>, block_time: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(block_time, time.timestamp_subsec_nanos())
+        .single()
+        .unwrap_or(time)
+}
+
+/// Latest decoded TokenBondingV0 state, one `account_updates` point per
+/// account write.
+pub fn account_update_point(
+    time: DateTime<Utc>,
+    pubkey: &Pubkey,
+    slot: u64,
+    write_version: u64,
+    token_bonding: &TokenBondingV0,
+) -> Point {
+    Point::new(ACCOUNT_UPDATES, account_update_id(pubkey, slot, write_version), time)
+        .tag("schema_version", SCHEMA_VERSION)
+        .tag("pubkey", pubkey)
+        .tag("base_mint", token_bonding.base_mint)
+        .tag("target_mint", token_bonding.target_mint)
+        .field("slot", slot)
+        .field("write_version", write_version)
+        .field_opt("general_authority", token_bonding.general_authority.map(|auth| auth.to_string()))
+        .field_opt("reserve_authority", token_bonding.reserve_authority.map(|auth| auth.to_string()))
+        .field_opt("curve_authority", token_bonding.curve_authority.map(|auth| auth.to_string()))
+        .field("base_storage", token_bonding.base_storage.to_string())
+        .field("buy_base_royalties", token_bonding.buy_base_royalties.to_string())
+        .field("buy_target_royalties", token_bonding.buy_target_royalties.to_string())
+        .field("sell_base_royalties", token_bonding.sell_base_royalties.to_string())
+        .field("sell_target_royalties", token_bonding.sell_target_royalties.to_string())
+        .field("buy_base_royalty_percentage", token_bonding.buy_base_royalty_percentage)
+        .field("buy_target_royalty_percentage", token_bonding.buy_target_royalty_percentage)
+        .field("sell_base_royalty_percentage", token_bonding.sell_base_royalty_percentage)
+        .field("sell_target_royalty_percentage", token_bonding.sell_target_royalty_percentage)
+        .field("curve", token_bonding.curve.to_string())
+        .field_opt("mint_cap", token_bonding.mint_cap)
+        .field_opt("purchase_cap", token_bonding.purchase_cap)
+        .field("go_live_unix_time", token_bonding.go_live_unix_time)
+        .field_opt("freeze_buy_unix_time", token_bonding.freeze_buy_unix_time)
+        .field("created_at_unix_time", token_bonding.created_at_unix_time)
+        .field("buy_frozen", token_bonding.buy_frozen)
+        .field("sell_frozen", token_bonding.sell_frozen)
+        .field("index", token_bonding.index)
+        .field("bump_seed", token_bonding.bump_seed)
+        .field("base_storage_bump_seed", token_bonding.base_storage_bump_seed)
+        .field("target_mint_authority_bump_seed", token_bonding.target_mint_authority_bump_seed)
+        .field_opt("base_storage_authority_bump_seed", token_bonding.base_storage_authority_bump_seed)
+        .field("reserve_balance_from_bonding", token_bonding.reserve_balance_from_bonding)
+        .field("supply_from_bonding", token_bonding.supply_from_bonding)
+        .field("ignore_external_reserve_changes", token_bonding.ignore_external_reserve_changes)
+        .field("ignore_external_supply_changes", token_bonding.ignore_external_supply_changes)
+}
+
+/// One `bonding_transactions` point per bonding program transaction.
+pub fn transaction_point(
+    time: DateTime<Utc>,
+    source: Source,
+    signature: &str,
+    slot: u64,
+    fee: u64,
+    compute_units_consumed: Option<u64>,
+) -> Point {
+    Point::new(BONDING_TRANSACTIONS, signature, time)
+        .tag("schema_version", SCHEMA_VERSION)
+        .field("source", source.as_str())
+        .field("signature", signature)
+        .field("slot", slot)
+        .field("fee", fee)
+        .field_opt("compute_units_consumed", compute_units_consumed)
+}
+
+/// Reserve and supply deltas of one buy or sell; UI amounts are in whole
+/// tokens, `_raw` amounts in base units.
+pub fn trade_point(
+    time: DateTime<Utc>,
+    source: Source,
+    signature: &str,
+    slot: u64,
+    instruction_index: u32,
+    inner_index: Option<u32>,
+    trade: &Trade,
+) -> Point {
+    Point::new(BONDING_TRADES, instruction_id(signature, instruction_index, inner_index), time)
+        .tag("schema_version", SCHEMA_VERSION)
+        .tag("side", trade.side.as_str())
+        .tag("pubkey", trade.token_bonding)
+        .tag("base_mint", trade.base_mint)
+        .tag("target_mint", trade.target_mint)
+        .field("source", source.as_str())
+        .field("signature", signature)
+        .field("slot", slot)
+        .field("instruction_index", instruction_index)
+        .field_opt("inner_index", inner_index)
+        .field("trader", trade.trader.to_string())
+        .field("reserve_change", trade.reserve_change_ui())
+        .field("supply_change", trade.supply_change_ui())
+        .field("reserve_change_raw", trade.reserve_change as i64)
+        .field("supply_change_raw", trade.supply_change as i64)
+        .field_opt("price", trade.price())
+        .field("base_royalties_paid", trade.base_royalties_paid_ui())
+        .field("target_royalties_paid", trade.target_royalties_paid_ui())
+        .field("trader_lamport_change", trade.trader_lamport_change as i64)
+}
+
+/// Marks every point with this `slot` field as belonging to a dead fork.
+pub fn orphaned_slot_point(time: DateTime<Utc>, slot: u64) -> Point {
+    Point::new(ORPHANED_SLOTS, slot.to_string(), time)
+        .tag("schema_version", SCHEMA_VERSION)
+        .field("slot", slot)
+}
+
+/// One `bonding_events` point per decoded instruction. Fields vary by event
+/// type; mints and the bonding account are tags so they can be filtered on.
+pub fn event_point(
+    slot: u64,
+    signature: &str,
+    ix: &DecodedInstruction,
+    time: DateTime<Utc>,
+    source: Source,
+) -> Point {
+    let id = instruction_id(signature, ix.instruction_index, ix.inner_index);
+    let mut point = Point::new(BONDING_EVENTS, id, time)
+        .tag("schema_version", SCHEMA_VERSION)
+        .tag("event_type", ix.event.event_type())
+        .field("source", source.as_str())
+        .field("signature", signature)
+        .field("slot", slot)
+        .field("instruction_index", ix.instruction_index)
+        .field_opt("inner_index", ix.inner_index);
+    if let Some(token_bonding) = ix.event.token_bonding() {
+        point = point.tag("pubkey", token_bonding);
+    }
+
+    match &ix.event {
+        BondingEvent::Buy { base_mint, target_mint, trader, native, amount, .. } => {
+            point = point
+                .tag("base_mint", base_mint)
+                .tag("target_mint", target_mint)
+                .field("trader", trader.to_string())
+                .field("native", *native);
+            match amount {
+                BuyAmount::WithBase { base_amount, minimum_target_amount } => {
+                    point = point
+                        .field("base_amount", *base_amount)
+                        .field("minimum_target_amount", *minimum_target_amount);
+                }
+                BuyAmount::TargetAmount { target_amount, maximum_price } => {
+                    point = point
+                        .field("target_amount", *target_amount)
+                        .field("maximum_price", *maximum_price);
+                }
+            }
+        }
+        BondingEvent::Sell { base_mint, target_mint, trader, native, target_amount, minimum_price, .. } => {
+            point = point
+                .tag("base_mint", base_mint)
+                .tag("target_mint", target_mint)
+                .field("trader", trader.to_string())
+                .field("native", *native)
+                .field("target_amount", *target_amount)
+                .field("minimum_price", *minimum_price);
+        }
+        BondingEvent::InitializeTokenBonding { base_mint, target_mint, curve, payer, .. } => {
+            point = point
+                .tag("base_mint", base_mint)
+                .tag("target_mint", target_mint)
+                .field("curve", curve.to_string())
+                .field("payer", payer.to_string());
+        }
+        BondingEvent::UpdateTokenBonding { general_authority, .. } => {
+            point = point.field("general_authority", general_authority.to_string());
+        }
+        BondingEvent::CloseTokenBonding { refund, .. } => {
+            point = point.field("refund", refund.to_string());
+        }
+        BondingEvent::UpdateReserveAuthority { new_reserve_authority, .. } => {
+            point = point.field_opt(
+                "new_reserve_authority",
+                new_reserve_authority.map(|authority| authority.to_string()),
+            );
+        }
+        BondingEvent::UpdateCurve { curve, curve_authority, .. } => {
+            point = point
+                .field("curve", curve.to_string())
+                .field_opt("curve_authority", curve_authority.map(|authority| authority.to_string()));
+        }
+        BondingEvent::TransferReserves { destination, native, amount, .. } => {
+            point = point
+                .field("destination", destination.to_string())
+                .field("native", *native)
+                .field("amount", *amount);
+        }
+        BondingEvent::CreateCurve { curve } => {
+            point = point.field("curve", curve.to_string());
+        }
+        BondingEvent::InitializeSolStorage => {}
+        BondingEvent::BuyWrappedSol { amount } => {
+            point = point.field("amount", *amount);
+        }
+        BondingEvent::SellWrappedSol { amount, all } => {
+            point = point.field("amount", *amount).field("all", *all);
+        }
+    }
+    point
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn points_of_one_second_get_distinct_times() {
+        // Three slots sharing a block time, each with transactions holding
+        // several points.
+        let mut times = HashSet::new();
+        for slot in 1_000..1_003 {
+            for index in 0..2_000 {
+                for ordinal in 0..4 {
+                    let time = point_time(1_700_000_000, slot, transaction_sequence(index, ordinal));
+                    assert!(times.insert(time), "slot {slot} index {index} ordinal {ordinal}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn point_time_keeps_the_block_second() {
+        let time = point_time(1_700_000_000, 123_456_789, transaction_sequence(99_999, 99));
+        assert_eq!(time.timestamp(), 1_700_000_000);
+        assert_eq!(time.timestamp_subsec_nanos(), 890_000_000 + 9_999_999);
+    }
+
+    #[test]
+    fn instruction_ids_tell_inner_instructions_apart() {
+        assert_eq!(instruction_id("sig", 2, None), "sig:2");
+        assert_eq!(instruction_id("sig", 2, Some(0)), "sig:2.0");
+        assert_ne!(instruction_id("sig", 2, Some(1)), instruction_id("sig", 21, None));
+    }
+
+    #[test]
+    fn account_update_ids_differ_per_write() {
+        let pubkey = Pubkey::new_unique();
+        assert_eq!(account_update_id(&pubkey, 7, 3), format!("{pubkey}:7:3"));
+        assert_ne!(account_update_id(&pubkey, 7, 3), account_update_id(&pubkey, 7, 4));
+    }
+
+    #[test]
+    fn restamped_points_match_their_backfilled_copies() {
+        let sequence = transaction_sequence(42, 1);
+        // Stamped live with an extrapolated second, then with the real one.
+        let live = restamp(point_time(1_700_000_003, 1_234, sequence), 1_700_000_001);
+        assert_eq!(live, point_time(1_700_000_001, 1_234, sequence));
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use influxdb::{Query, Timestamp, Type, WriteQuery};
+use std::time::Duration;
+
+use super::{Sink, SinkError};
+use crate::point::{FieldValue, Point};
+
+#[derive(Debug, Clone)]
+pub struct InfluxConfig {
+    /// Base URL of the InfluxDB server.
+    pub url: String,
+    /// InfluxDB 2 organization. Without it the 1.x `/write` endpoint is used
+    /// and `bucket` is the database name.
+    pub org: Option<String>,
+    pub bucket: String,
+    pub token: Option<String>,
+}
+
+/// Writes line protocol over HTTP.
+pub struct InfluxSink {
+    http: reqwest::Client,
+    config: InfluxConfig,
+}
+
+impl InfluxSink {
+    pub fn new(config: InfluxConfig) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(Self { http, config })
+    }
+
+    fn request(&self, lines: String) -> reqwest::RequestBuilder {
+        let url = self.config.url.trim_end_matches('/');
+        let request = match &self.config.org {
+            Some(org) => self.http.post(format!("{url}/api/v2/write")).query(&[
+                ("org", org.as_str()),
+                ("bucket", self.config.bucket.as_str()),
+                ("precision", "ns"),
+            ]),
+            None => self
+                .http
+                .post(format!("{url}/write"))
+                .query(&[("db", self.config.bucket.as_str()), ("precision", "ns")]),
+        };
+        let request = request.body(lines);
+        match &self.config.token {
+            Some(token) => request.header("Authorization", format!("Token {token}")),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for InfluxSink {
+    fn name(&self) -> &str {
+        "influx"
+    }
+
+    async fn write(&self, points: &[Point]) -> Result<(), SinkError> {
+        let lines = render(points).map_err(SinkError::Fatal)?;
+        let response = self.request(lines).send().await.map_err(|error| {
+            if error.is_timeout() || error.is_connect() || error.is_request() {
+                SinkError::Retryable(error.into())
+            } else {
+                SinkError::Fatal(error.into())
+            }
+        })?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = response.text().await.unwrap_or_default();
+        let error = anyhow!("influx responded {status}: {body}");
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(SinkError::Retryable(error))
+        } else {
+            Err(SinkError::Fatal(error))
+        }
+    }
+}
+
+fn render(points: &[Point]) -> anyhow::Result<String> {
+    let lines = points
+        .iter()
+        .map(|point| Ok(to_query(point).build().context("invalid point")?.get()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(lines.join("\n"))
+}
+
+fn to_query(point: &Point) -> WriteQuery {
+    let nanos = point.time.timestamp_nanos_opt().unwrap_or_default().max(0);
+    let mut query = WriteQuery::new(Timestamp::Nanoseconds(nanos as u128), point.measurement.as_str());
+    for (key, value) in &point.tags {
+        query = query.add_tag(key.as_str(), value.as_str());
+    }
+    // A field, not a tag: one series per ID would explode cardinality, and
+    // duplicates already overwrite each other by series and timestamp.
+    if !point.id.is_empty() {
+        query = query.add_field("id", Type::Text(point.id.clone()));
+    }
+    for (key, value) in &point.fields {
+        let value = match value {
+            FieldValue::Bool(value) => Type::Boolean(*value),
+            FieldValue::UnsignedInteger(value) => Type::UnsignedInteger(*value),
+            FieldValue::Integer(value) => Type::SignedInteger(*value),
+            FieldValue::Float(value) => Type::Float(*value),
+            FieldValue::Text(value) => Type::Text(value.clone()),
+        };
+        query = query.add_field(key.as_str(), value);
+    }
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn renders_line_protocol_with_the_id_as_a_field() {
+        let time = Utc.timestamp_opt(1_700_000_000, 5).unwrap();
+        let trade = Point::new("bonding_trades", "sig:0", time)
+            .tag("side", "sell")
+            .tag("pubkey", "bonding")
+            .field("trader", "wallet")
+            .field("reserve_change_raw", -1_000i64)
+            .field("price", 0.5)
+            .field("native", true);
+        let slot = Point::new("orphaned_slots", "7", time).tag("reason", "dead fork");
+
+        assert_eq!(
+            render(&[trade, slot]).unwrap(),
+            "bonding_trades,pubkey=bonding,side=sell \
+             id=\"sig:0\",native=true,price=0.5,reserve_change_raw=-1000i,trader=\"wallet\" 1700000000000000005\n\
+             orphaned_slots,reason=dead\\ fork id=\"7\" 1700000000000000005"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+use super::{Sink, SinkError};
+use crate::dedupe::RecentIds;
+use crate::point::Point;
+
+#[derive(Debug, Clone)]
+pub struct JsonConfig {
+    /// File to append to; `-` writes to stdout.
+    pub path: PathBuf,
+}
+
+/// Newline-delimited JSON, one point per line. Appending cannot overwrite,
+/// so points written recently are skipped.
+pub struct JsonSink {
+    config: JsonConfig,
+    recent: Mutex<RecentIds>,
+}
+
+impl JsonSink {
+    pub fn new(config: JsonConfig) -> Self {
+        Self {
+            config,
+            recent: Mutex::new(RecentIds::default()),
+        }
+    }
+
+    async fn append(&self, lines: &[u8]) -> anyhow::Result<()> {
+        if self.config.path.as_os_str() == "-" {
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(lines).await?;
+            stdout.flush().await?;
+            return Ok(());
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .await
+            .with_context(|| format!("failed to open {}", self.config.path.display()))?;
+        file.write_all(lines).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for JsonSink {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    async fn write(&self, points: &[Point]) -> Result<(), SinkError> {
+        let mut recent = self.recent.lock().await;
+        let fresh: Vec<&Point> = points.iter().filter(|point| !recent.contains(point)).collect();
+        let lines = render(&fresh).map_err(|error| SinkError::Fatal(error.into()))?;
+        self.append(&lines).await.map_err(SinkError::Retryable)?;
+        for point in fresh {
+            recent.insert(point);
+        }
+        Ok(())
+    }
+}
+
+/// One JSON object per point, each followed by a newline.
+fn render(points: &[&Point]) -> serde_json::Result<Vec<u8>> {
+    let mut lines = Vec::new();
+    for point in points {
+        serde_json::to_writer(&mut lines, point)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn renders_one_line_per_point() {
+        let time = Utc.timestamp_opt(1_700_000_000, 5).unwrap();
+        let first = Point::new("slots", "1", time).tag("schema_version", "3").field("slot", 1u64);
+        let second = Point::new("slots", "2", time).field("native", true).field("price", 0.5);
+        let lines = String::from_utf8(render(&[&first, &second]).unwrap()).unwrap();
+
+        assert!(lines.ends_with('\n'));
+        let parsed: Vec<Point> = lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(parsed, vec![first, second]);
+        assert!(lines.starts_with(r#"{"measurement":"slots","id":"1","time":"2023-11-14T22:13:20.000000005Z""#));
+    }
+
+    #[test]
+    fn renders_nothing_for_no_points() {
+        assert!(render(&[]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,64 @@
+//! Destinations for the points produced by the pipeline. Each configured sink
+//! gets its own [`crate::writer::SinkWriter`], so a slow or unavailable sink
+//! only spools its own backlog and never holds up the others.
+
+mod amqp;
+mod influx;
+mod json;
+mod postgres;
+
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::point::Point;
+
+pub use amqp::{AmqpConfig, AmqpSink};
+pub use influx::{InfluxConfig, InfluxSink};
+pub use json::{JsonConfig, JsonSink};
+pub use postgres::{PostgresConfig, PostgresSink};
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// Short identifier used in logs and for the sink's spool file.
+    fn name(&self) -> &str;
+
+    /// Writes one batch. The batch is retried as a whole on
+    /// [`SinkError::Retryable`], so writes should be idempotent per point.
+    async fn write(&self, points: &[Point]) -> Result<(), SinkError>;
+}
+
+#[derive(Debug)]
+pub enum SinkError {
+    /// Timeouts, connection failures, overload: worth another attempt.
+    Retryable(anyhow::Error),
+    /// Anything else, e.g. a malformed point; retrying would fail the same way.
+    Fatal(anyhow::Error),
+}
+
+impl std::fmt::Display for SinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SinkError::Retryable(error) => write!(f, "retryable: {error:#}"),
+            SinkError::Fatal(error) => write!(f, "fatal: {error:#}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SinkConfig {
+    Influx(InfluxConfig),
+    Postgres(PostgresConfig),
+    Amqp(AmqpConfig),
+    Json(JsonConfig),
+}
+
+impl SinkConfig {
+    pub fn build(self) -> anyhow::Result<Arc<dyn Sink>> {
+        Ok(match self {
+            SinkConfig::Influx(config) => Arc::new(InfluxSink::new(config)?),
+            SinkConfig::Postgres(config) => Arc::new(PostgresSink::new(config)),
+            SinkConfig::Amqp(config) => Arc::new(AmqpSink::new(config)),
+            SinkConfig::Json(config) => Arc::new(JsonSink::new(config)),
+        })
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use std::collections::HashSet;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, NoTls};
+use tracing::warn;
+
+use super::{Sink, SinkError};
+use crate::point::Point;
+
+#[derive(Debug, Clone)]
+pub struct PostgresConfig {
+    /// libpq-style connection string, e.g. `host=db user=consumer dbname=bonding`.
+    pub url: String,
+    pub table: String,
+    /// Turn the table into a TimescaleDB hypertable on first connect.
+    pub timescale: bool,
+}
+
+/// Stores every point as one row with JSONB tags and fields, so all
+/// measurements share a single table:
+///
+/// ```sql
+/// CREATE TABLE bonding_points (
+///     measurement text        NOT NULL,
+///     id          text        NOT NULL,
+///     time        timestamptz NOT NULL,
+///     tags        jsonb       NOT NULL,
+///     fields      jsonb       NOT NULL
+/// );
+/// ```
+///
+/// Rows are upserted on `(measurement, id)`, plus `time` for hypertables,
+/// whose unique indexes must include the partitioning column.
+pub struct PostgresSink {
+    config: PostgresConfig,
+    /// Connected lazily and dropped after a connection error so the next
+    /// batch reconnects.
+    client: Mutex<Option<Client>>,
+}
+
+impl PostgresSink {
+    pub fn new(config: PostgresConfig) -> Self {
+        Self {
+            config,
+            client: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> anyhow::Result<Client> {
+        let (client, connection) = tokio_postgres::connect(&self.config.url, NoTls)
+            .await
+            .context("failed to connect to postgres")?;
+        tokio::spawn(async move {
+            if let Err(error) = connection.await {
+                warn!(?error, "postgres connection closed");
+            }
+        });
+
+        let table = &self.config.table;
+        let key = self.conflict_key();
+        client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {table} (
+                    measurement text NOT NULL,
+                    id text NOT NULL,
+                    time timestamptz NOT NULL,
+                    tags jsonb NOT NULL,
+                    fields jsonb NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS {table}_measurement_time_idx ON {table} (measurement, time DESC);"
+            ))
+            .await
+            .with_context(|| format!("failed to create table {table}"))?;
+        if self.config.timescale {
+            let result = client
+                .execute(
+                    "SELECT create_hypertable($1::text::regclass, 'time', if_not_exists => TRUE)",
+                    &[table],
+                )
+                .await;
+            if let Err(error) = result {
+                warn!(%table, ?error, "failed to create hypertable, continuing without");
+            }
+        }
+        // After the hypertable conversion, which rejects tables with unique
+        // indexes lacking `time`.
+        client
+            .batch_execute(&format!(
+                "CREATE UNIQUE INDEX IF NOT EXISTS {table}_id_idx ON {table} ({key});"
+            ))
+            .await
+            .with_context(|| format!("failed to create unique index on {table}"))?;
+        Ok(client)
+    }
+
+    fn conflict_key(&self) -> &'static str {
+        if self.config.timescale {
+            "measurement, id, time"
+        } else {
+            "measurement, id"
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for PostgresSink {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    async fn write(&self, points: &[Point]) -> Result<(), SinkError> {
+        let mut client = self.client.lock().await;
+        if client.as_ref().map_or(true, Client::is_closed) {
+            *client = Some(self.connect().await.map_err(SinkError::Retryable)?);
+        }
+        let rows = serde_json::to_value(dedupe(points)).map_err(|error| SinkError::Fatal(error.into()))?;
+
+        let statement = insert_statement(&self.config.table, self.conflict_key());
+        let result = client
+            .as_ref()
+            .expect("connected above")
+            .execute(&statement, &[&rows])
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(error) if is_retryable(&error) => {
+                *client = None;
+                Err(SinkError::Retryable(error.into()))
+            }
+            Err(error) => Err(SinkError::Fatal(error.into())),
+        }
+    }
+}
+
+/// One round trip per batch: the points, bound as a JSON array to `$1`, are
+/// expanded server side.
+fn insert_statement(table: &str, conflict_key: &str) -> String {
+    format!(
+        "INSERT INTO {table} (measurement, id, time, tags, fields)
+             SELECT measurement, id, time, tags, fields
+             FROM jsonb_to_recordset($1)
+                 AS p(measurement text, id text, time timestamptz, tags jsonb, fields jsonb)
+             ON CONFLICT ({conflict_key}) DO UPDATE SET tags = EXCLUDED.tags, fields = EXCLUDED.fields"
+    )
+}
+
+/// Last copy of every `(measurement, id)` in the batch, since ON CONFLICT
+/// cannot update the same row twice in one statement.
+fn dedupe(points: &[Point]) -> Vec<&Point> {
+    let mut seen = HashSet::new();
+    let mut unique: Vec<&Point> = points
+        .iter()
+        .rev()
+        .filter(|point| seen.insert((&point.measurement, &point.id)))
+        .collect();
+    unique.reverse();
+    unique
+}
+
+/// Connection problems, serialization failures and resource exhaustion are
+/// transient; constraint and data errors are not.
+fn is_retryable(error: &tokio_postgres::Error) -> bool {
+    let Some(db_error) = error.as_db_error() else {
+        return true;
+    };
+    let class = &db_error.code().code()[..2];
+    matches!(class, "08" | "40" | "53" | "57")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn point(measurement: &str, id: &str, price: f64) -> Point {
+        let time = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        Point::new(measurement, id, time).tag("side", "buy").field("price", price)
+    }
+
+    #[test]
+    fn inserts_upsert_on_the_conflict_key() {
+        let statement = insert_statement("bonding_points", "measurement, id");
+        assert!(statement.starts_with("INSERT INTO bonding_points (measurement, id, time, tags, fields)"));
+        assert!(statement.contains("FROM jsonb_to_recordset($1)"));
+        assert!(statement.ends_with(
+            "ON CONFLICT (measurement, id) DO UPDATE SET tags = EXCLUDED.tags, fields = EXCLUDED.fields"
+        ));
+    }
+
+    #[test]
+    fn rows_match_the_recordset_columns() {
+        let rows = serde_json::to_value(dedupe(&[point("bonding_trades", "sig:0", 0.5)])).unwrap();
+        assert_eq!(
+            rows,
+            serde_json::json!([{
+                "measurement": "bonding_trades",
+                "id": "sig:0",
+                "time": "2023-11-14T22:13:20Z",
+                "tags": {"side": "buy"},
+                "fields": {"price": 0.5},
+            }])
+        );
+    }
+
+    #[test]
+    fn dedupe_keeps_the_last_copy_in_order() {
+        let points = [
+            point("bonding_trades", "a", 1.0),
+            point("bonding_events", "a", 2.0),
+            point("bonding_trades", "b", 3.0),
+            point("bonding_trades", "a", 4.0),
+        ];
+        let unique: Vec<&Point> = dedupe(&points);
+        assert_eq!(unique, vec![&points[1], &points[2], &points[3]]);
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+
+/// Nominal slot duration used to extrapolate between known block times.
+const SLOT_DURATION_MS: i64 = 400;
+/// Known block times kept around; older slots are extrapolated.
+const CAPACITY: usize = 4096;
+
+/// Maps slots to block times from `BlockMeta` updates.
+///
+/// Account and transaction updates for a slot arrive before its block meta,
+/// so the clock extrapolates from the nearest known slot until the real time
+/// shows up.
+#[derive(Debug, Default)]
+pub struct SlotClock {
+    block_times: BTreeMap<u64, i64>,
+}
+
+impl SlotClock {
+    pub fn record(&mut self, slot: u64, block_time: i64) {
+        self.block_times.insert(slot, block_time);
+        while self.block_times.len() > CAPACITY {
+            self.block_times.pop_first();
+        }
+    }
+
+    /// Block time reported for exactly `slot`, never extrapolated.
+    pub fn recorded(&self, slot: u64) -> Option<i64> {
+        self.block_times.get(&slot).copied()
+    }
+
+    /// Newest slot with a recorded block time.
+    pub fn latest(&self) -> Option<u64> {
+        self.block_times.keys().next_back().copied()
+    }
+
+    /// Unix time of `slot` in seconds, or `None` before any block meta arrived.
+    pub fn block_time(&self, slot: u64) -> Option<i64> {
+        if let Some(block_time) = self.block_times.get(&slot) {
+            return Some(*block_time);
+        }
+        let (known_slot, known_time) = self
+            .block_times
+            .range(..slot)
+            .next_back()
+            .or_else(|| self.block_times.range(slot..).next())?;
+        let elapsed_ms = (slot as i64 - *known_slot as i64) * SLOT_DURATION_MS;
+        Some(known_time + elapsed_ms / 1000)
+    }
+}
@@ -0,0 +1,72 @@
+use chrono::Utc;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+
+/// Stream progress shared with the admin endpoint. Written by the stream loop
+/// only; everything else reads snapshots.
+#[derive(Debug, Default)]
+pub struct StreamStatus {
+    /// 0 until the first slot is processed.
+    last_slot: AtomicU64,
+    /// Block time of `last_slot` in unix seconds, 0 when unknown.
+    last_block_time: AtomicI64,
+    /// Highest slot announced by the server at any commitment.
+    tip_slot: AtomicU64,
+    reconnects: AtomicU64,
+    connected: AtomicBool,
+    /// Unix milliseconds of the last message from the server, 0 before any.
+    last_message_ms: AtomicI64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusSnapshot {
+    pub last_slot: Option<u64>,
+    /// Seconds between now and the block time of `last_slot`.
+    pub lag_seconds: Option<i64>,
+    pub tip_slot: Option<u64>,
+    /// Slots between the tip and `last_slot`.
+    pub slots_behind: Option<u64>,
+    pub reconnects: u64,
+    pub connected: bool,
+    pub seconds_since_last_message: Option<f64>,
+}
+
+impl StreamStatus {
+    pub fn set_last_slot(&self, slot: u64, block_time: i64) {
+        self.last_slot.store(slot, Ordering::Relaxed);
+        self.last_block_time.store(block_time, Ordering::Relaxed);
+    }
+
+    pub fn set_tip_slot(&self, slot: u64) {
+        self.tip_slot.fetch_max(slot, Ordering::Relaxed);
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn set_last_message(&self) {
+        self.last_message_ms.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub fn set_reconnects(&self, reconnects: u64) {
+        self.reconnects.store(reconnects, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatusSnapshot {
+        let last_slot = self.last_slot.load(Ordering::Relaxed);
+        let last_block_time = self.last_block_time.load(Ordering::Relaxed);
+        let tip_slot = self.tip_slot.load(Ordering::Relaxed);
+        let last_message_ms = self.last_message_ms.load(Ordering::Relaxed);
+        StatusSnapshot {
+            last_slot: (last_slot > 0).then_some(last_slot),
+            lag_seconds: (last_block_time > 0).then(|| Utc::now().timestamp() - last_block_time),
+            tip_slot: (tip_slot > 0).then_some(tip_slot),
+            slots_behind: (last_slot > 0 && tip_slot > 0).then(|| tip_slot.saturating_sub(last_slot)),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            connected: self.connected.load(Ordering::Relaxed),
+            seconds_since_last_message: (last_message_ms > 0)
+                .then(|| (Utc::now().timestamp_millis() - last_message_ms) as f64 / 1000.0),
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use solana_sdk::pubkey::Pubkey;
+use yellowstone_grpc_proto::prelude::{TokenBalance, TransactionStatusMeta};
+
+use crate::decoder::{BondingEvent, DecodedInstruction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Buy => "buy",
+            Side::Sell => "sell",
+        }
+    }
+}
+
+/// Balance changes caused by one buy or sell, in raw token units unless noted.
+///
+/// Balances are only known per transaction, so when a transaction trades the
+/// same bonding more than once every trade carries the combined deltas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+    pub side: Side,
+    pub token_bonding: Pubkey,
+    pub base_mint: Pubkey,
+    pub target_mint: Pubkey,
+    pub trader: Pubkey,
+    /// Change of the base storage balance; positive on buys.
+    pub reserve_change: i128,
+    /// Change of the target mint supply; positive on buys.
+    pub supply_change: i128,
+    pub base_decimals: u32,
+    pub target_decimals: u32,
+    pub base_royalties_paid: u128,
+    pub target_royalties_paid: u128,
+    /// Lamport change of the trader wallet, fees included.
+    pub trader_lamport_change: i128,
+}
+
+impl Trade {
+    pub fn reserve_change_ui(&self) -> f64 {
+        to_ui(self.reserve_change, self.base_decimals)
+    }
+
+    pub fn supply_change_ui(&self) -> f64 {
+        to_ui(self.supply_change, self.target_decimals)
+    }
+
+    pub fn base_royalties_paid_ui(&self) -> f64 {
+        to_ui(self.base_royalties_paid as i128, self.base_decimals)
+    }
+
+    pub fn target_royalties_paid_ui(&self) -> f64 {
+        to_ui(self.target_royalties_paid as i128, self.target_decimals)
+    }
+
+    /// Average price the curve charged or paid, in base per target token.
+    ///
+    /// A buy mints the target royalties on top of the purchase, so they are
+    /// taken out of the supply change. A sell burns only what is left after
+    /// the target royalties and pays base royalties out of the reserve change,
+    /// so both sides already match the curve.
+    pub fn price(&self) -> Option<f64> {
+        let curve_supply_change = match self.side {
+            Side::Buy => self.supply_change - self.target_royalties_paid as i128,
+            Side::Sell => self.supply_change,
+        };
+        let curve_supply_change = to_ui(curve_supply_change, self.target_decimals);
+        (curve_supply_change != 0.0).then(|| self.reserve_change_ui() / curve_supply_change)
+    }
+}
+
+fn to_ui(amount: i128, decimals: u32) -> f64 {
+    amount as f64 / 10f64.powi(decimals as i32)
+}
+
+/// Computes the trade for a decoded buy or sell from the transaction's token
+/// and lamport balances; `keys` is the resolved account list of the transaction.
+pub fn compute_trade(
+    ix: &DecodedInstruction,
+    keys: &[Pubkey],
+    meta: &TransactionStatusMeta,
+) -> Option<Trade> {
+    let balances = Balances { keys, meta };
+    let (side, accounts) = match &ix.event {
+        BondingEvent::Buy {
+            token_bonding,
+            base_mint,
+            target_mint,
+            base_storage,
+            base_royalties,
+            target_royalties,
+            destination,
+            trader,
+            ..
+        } => (
+            Side::Buy,
+            TradeAccounts {
+                token_bonding,
+                base_mint,
+                target_mint,
+                base_storage,
+                base_royalties,
+                target_royalties,
+                trader_token_account: destination,
+                trader,
+            },
+        ),
+        BondingEvent::Sell {
+            token_bonding,
+            base_mint,
+            target_mint,
+            base_storage,
+            base_royalties,
+            target_royalties,
+            source,
+            trader,
+            ..
+        } => (
+            Side::Sell,
+            TradeAccounts {
+                token_bonding,
+                base_mint,
+                target_mint,
+                base_storage,
+                base_royalties,
+                target_royalties,
+                trader_token_account: source,
+                trader,
+            },
+        ),
+        _ => return None,
+    };
+
+    // A royalty account that is also the trader's own token account cannot be
+    // told apart from the trade itself, so it counts as no royalty.
+    let royalty = |account: &Pubkey| -> u128 {
+        if account == accounts.trader_token_account || account == accounts.trader {
+            return 0;
+        }
+        balances.token_delta(account).unsigned_abs()
+    };
+
+    Some(Trade {
+        side,
+        token_bonding: *accounts.token_bonding,
+        base_mint: *accounts.base_mint,
+        target_mint: *accounts.target_mint,
+        trader: *accounts.trader,
+        reserve_change: balances.token_delta(accounts.base_storage),
+        supply_change: balances.mint_delta(accounts.target_mint),
+        base_decimals: balances.decimals(accounts.base_mint).unwrap_or(0),
+        target_decimals: balances.decimals(accounts.target_mint).unwrap_or(0),
+        base_royalties_paid: royalty(accounts.base_royalties),
+        target_royalties_paid: royalty(accounts.target_royalties),
+        trader_lamport_change: balances.lamport_delta(accounts.trader),
+    })
+}
+
+struct TradeAccounts<'a> {
+    token_bonding: &'a Pubkey,
+    base_mint: &'a Pubkey,
+    target_mint: &'a Pubkey,
+    base_storage: &'a Pubkey,
+    base_royalties: &'a Pubkey,
+    target_royalties: &'a Pubkey,
+    /// Destination of a buy or source of a sell.
+    trader_token_account: &'a Pubkey,
+    trader: &'a Pubkey,
+}
+
+struct Balances<'a> {
+    keys: &'a [Pubkey],
+    meta: &'a TransactionStatusMeta,
+}
+
+impl Balances<'_> {
+    fn index_of(&self, account: &Pubkey) -> Option<usize> {
+        self.keys.iter().position(|key| key == account)
+    }
+
+    fn amount(balances: &[TokenBalance], account_index: u32) -> i128 {
+        balances
+            .iter()
+            .find(|balance| balance.account_index == account_index)
+            .and_then(|balance| balance.ui_token_amount.as_ref())
+            .and_then(|amount| amount.amount.parse::<i128>().ok())
+            .unwrap_or(0)
+    }
+
+    /// Post minus pre token balance of `account`; accounts created or closed
+    /// in the transaction count as zero on the missing side.
+    fn token_delta(&self, account: &Pubkey) -> i128 {
+        let Some(index) = self.index_of(account) else {
+            return 0;
+        };
+        let index = index as u32;
+        Self::amount(&self.meta.post_token_balances, index)
+            - Self::amount(&self.meta.pre_token_balances, index)
+    }
+
+    /// Net change over every token account of `mint`, i.e. minted minus burned.
+    fn mint_delta(&self, mint: &Pubkey) -> i128 {
+        let mint = mint.to_string();
+        let mut indexes: Vec<u32> = self
+            .meta
+            .pre_token_balances
+            .iter()
+            .chain(&self.meta.post_token_balances)
+            .filter(|balance| balance.mint == mint)
+            .map(|balance| balance.account_index)
+            .collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes
+            .into_iter()
+            .map(|index| {
+                Self::amount(&self.meta.post_token_balances, index)
+                    - Self::amount(&self.meta.pre_token_balances, index)
+            })
+            .sum()
+    }
+
+    fn decimals(&self, mint: &Pubkey) -> Option<u32> {
+        let mint = mint.to_string();
+        self.meta
+            .pre_token_balances
+            .iter()
+            .chain(&self.meta.post_token_balances)
+            .find(|balance| balance.mint == mint)
+            .and_then(|balance| balance.ui_token_amount.as_ref())
+            .map(|amount| amount.decimals)
+    }
+
+    fn lamport_delta(&self, account: &Pubkey) -> i128 {
+        let Some(index) = self.index_of(account) else {
+            return 0;
+        };
+        let pre = self.meta.pre_balances.get(index).copied().unwrap_or(0);
+        let post = self.meta.post_balances.get(index).copied().unwrap_or(0);
+        post as i128 - pre as i128
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::BuyAmount;
+    use yellowstone_grpc_proto::prelude::UiTokenAmount;
+
+    const BASE_DECIMALS: u32 = 9;
+    const TARGET_DECIMALS: u32 = 6;
+
+    struct Accounts {
+        keys: Vec<Pubkey>,
+    }
+
+    impl Accounts {
+        fn new() -> Self {
+            Self {
+                keys: (0..10).map(|i| Pubkey::new_from_array([i; 32])).collect(),
+            }
+        }
+        fn trader(&self) -> Pubkey {
+            self.keys[0]
+        }
+        fn token_bonding(&self) -> Pubkey {
+            self.keys[1]
+        }
+        fn base_mint(&self) -> Pubkey {
+            self.keys[2]
+        }
+        fn target_mint(&self) -> Pubkey {
+            self.keys[3]
+        }
+        fn base_storage(&self) -> Pubkey {
+            self.keys[4]
+        }
+        fn base_royalties(&self) -> Pubkey {
+            self.keys[5]
+        }
+        fn trader_target(&self) -> Pubkey {
+            self.keys[6]
+        }
+        fn target_royalties(&self) -> Pubkey {
+            self.keys[7]
+        }
+    }
+
+    fn balance(index: u32, mint: &Pubkey, decimals: u32, amount: u64) -> TokenBalance {
+        TokenBalance {
+            account_index: index,
+            mint: mint.to_string(),
+            ui_token_amount: Some(UiTokenAmount {
+                decimals,
+                amount: amount.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// `(account index, mint, decimals, pre, post)` per token account.
+    fn meta(accounts: &Accounts, balances: &[(u32, Pubkey, u32, u64, u64)]) -> TransactionStatusMeta {
+        let mut pre_balances = vec![0; accounts.keys.len()];
+        let mut post_balances = vec![0; accounts.keys.len()];
+        pre_balances[0] = 2_000_000_000;
+        post_balances[0] = 1_999_995_000;
+        TransactionStatusMeta {
+            pre_balances,
+            post_balances,
+            pre_token_balances: balances
+                .iter()
+                .map(|(index, mint, decimals, pre, _)| balance(*index, mint, *decimals, *pre))
+                .collect(),
+            post_token_balances: balances
+                .iter()
+                .map(|(index, mint, decimals, _, post)| balance(*index, mint, *decimals, *post))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn buy(accounts: &Accounts) -> DecodedInstruction {
+        DecodedInstruction {
+            instruction_index: 0,
+            inner_index: None,
+            event: BondingEvent::Buy {
+                token_bonding: accounts.token_bonding(),
+                base_mint: accounts.base_mint(),
+                target_mint: accounts.target_mint(),
+                base_storage: accounts.base_storage(),
+                base_royalties: accounts.base_royalties(),
+                target_royalties: accounts.target_royalties(),
+                destination: accounts.trader_target(),
+                trader: accounts.trader(),
+                native: false,
+                amount: BuyAmount::TargetAmount {
+                    target_amount: 950_000,
+                    maximum_price: u64::MAX,
+                },
+            },
+        }
+    }
+
+    fn sell(accounts: &Accounts) -> DecodedInstruction {
+        DecodedInstruction {
+            instruction_index: 0,
+            inner_index: None,
+            event: BondingEvent::Sell {
+                token_bonding: accounts.token_bonding(),
+                base_mint: accounts.base_mint(),
+                target_mint: accounts.target_mint(),
+                base_storage: accounts.base_storage(),
+                base_royalties: accounts.base_royalties(),
+                target_royalties: accounts.target_royalties(),
+                source: accounts.trader_target(),
+                trader: accounts.trader(),
+                native: false,
+                target_amount: 1_000_000,
+                minimum_price: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn buy_deltas_and_price() {
+        let accounts = Accounts::new();
+        let (base, target) = (accounts.base_mint(), accounts.target_mint());
+        // 0.95 target to the buyer and 0.05 to the royalty account for 0.5
+        // base into the reserve plus 0.01 base royalties.
+        let meta = meta(
+            &accounts,
+            &[
+                (4, base, BASE_DECIMALS, 1_000_000_000, 1_500_000_000),
+                (5, base, BASE_DECIMALS, 0, 10_000_000),
+                (8, base, BASE_DECIMALS, 600_000_000, 90_000_000),
+                (6, target, TARGET_DECIMALS, 0, 950_000),
+                (7, target, TARGET_DECIMALS, 0, 50_000),
+            ],
+        );
+        let trade = compute_trade(&buy(&accounts), &accounts.keys, &meta).unwrap();
+
+        assert_eq!(trade.side, Side::Buy);
+        assert_eq!(trade.token_bonding, accounts.token_bonding());
+        assert_eq!(trade.reserve_change, 500_000_000);
+        assert_eq!(trade.supply_change, 1_000_000);
+        assert_eq!(trade.base_royalties_paid, 10_000_000);
+        assert_eq!(trade.target_royalties_paid, 50_000);
+        assert_eq!((trade.base_decimals, trade.target_decimals), (BASE_DECIMALS, TARGET_DECIMALS));
+        assert_eq!(trade.trader_lamport_change, -5_000);
+        assert_eq!(trade.reserve_change_ui(), 0.5);
+        assert_eq!(trade.supply_change_ui(), 1.0);
+        let price = trade.price().unwrap();
+        assert!((price - 0.5 / 0.95).abs() < 1e-12, "{price}");
+    }
+
+    #[test]
+    fn sell_deltas_and_price() {
+        let accounts = Accounts::new();
+        let (base, target) = (accounts.base_mint(), accounts.target_mint());
+        // 1 target sold: 0.05 to the royalty account, 0.95 burned for 0.475
+        // base out of the reserve.
+        let meta = meta(
+            &accounts,
+            &[
+                (4, base, BASE_DECIMALS, 1_500_000_000, 1_025_000_000),
+                (5, base, BASE_DECIMALS, 0, 4_750_000),
+                (8, base, BASE_DECIMALS, 0, 470_250_000),
+                (6, target, TARGET_DECIMALS, 1_000_000, 0),
+                (7, target, TARGET_DECIMALS, 0, 50_000),
+            ],
+        );
+        let trade = compute_trade(&sell(&accounts), &accounts.keys, &meta).unwrap();
+
+        assert_eq!(trade.side, Side::Sell);
+        assert_eq!(trade.reserve_change, -475_000_000);
+        assert_eq!(trade.supply_change, -950_000);
+        assert_eq!(trade.base_royalties_paid, 4_750_000);
+        assert_eq!(trade.target_royalties_paid, 50_000);
+        let price = trade.price().unwrap();
+        assert!((price - 0.5).abs() < 1e-12, "{price}");
+    }
+
+    #[test]
+    fn royalties_to_the_trader_count_as_none() {
+        let accounts = Accounts::new();
+        let mut ix = buy(&accounts);
+        if let BondingEvent::Buy { target_royalties, .. } = &mut ix.event {
+            *target_royalties = accounts.trader_target();
+        }
+        let meta = meta(
+            &accounts,
+            &[
+                (4, accounts.base_mint(), BASE_DECIMALS, 0, 500_000_000),
+                (6, accounts.target_mint(), TARGET_DECIMALS, 0, 1_000_000),
+            ],
+        );
+        let trade = compute_trade(&ix, &accounts.keys, &meta).unwrap();
+        assert_eq!(trade.target_royalties_paid, 0);
+        assert_eq!(trade.price(), Some(0.5));
+    }
+
+    #[test]
+    fn no_supply_change_has_no_price() {
+        let accounts = Accounts::new();
+        let meta = meta(&accounts, &[(4, accounts.base_mint(), BASE_DECIMALS, 0, 1)]);
+        let trade = compute_trade(&buy(&accounts), &accounts.keys, &meta).unwrap();
+        assert_eq!(trade.price(), None);
+    }
+
+    #[test]
+    fn other_events_are_not_trades() {
+        let accounts = Accounts::new();
+        let ix = DecodedInstruction {
+            instruction_index: 0,
+            inner_index: None,
+            event: BondingEvent::InitializeSolStorage,
+        };
+        assert_eq!(compute_trade(&ix, &accounts.keys, &meta(&accounts, &[])), None);
+    }
+}
@@ -0,0 +1,758 @@
+use anyhow::Context;
+use serde::Deserialize;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+use tracing::{error, info, warn};
+
+use crate::backoff::Backoff;
+use crate::metrics;
+use crate::point::Point;
+use crate::sink::{Sink, SinkError};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriterConfig {
+    /// Points buffered between ingestion and each sink's writer task.
+    pub channel_capacity: usize,
+    /// Flush as soon as this many points are buffered...
+    pub batch_size: usize,
+    /// ...or this long after the previous flush.
+    #[serde(rename = "flush_interval_ms", deserialize_with = "crate::config::millis")]
+    pub flush_interval: Duration,
+    /// Attempts per batch before it is spilled to disk.
+    pub max_attempts: u32,
+    /// Directory holding one NDJSON spool file per sink for batches that
+    /// could not be written.
+    pub spool_dir: PathBuf,
+    /// How long shutdown waits for the writers to flush.
+    #[serde(rename = "drain_timeout_ms", deserialize_with = "crate::config::millis")]
+    pub drain_timeout: Duration,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            channel_capacity: 10_000,
+            batch_size: 500,
+            flush_interval: Duration::from_secs(1),
+            max_attempts: 5,
+            spool_dir: "spool".into(),
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Fans every point out to all configured sinks.
+#[derive(Clone)]
+pub struct Sinks {
+    writers: Arc<Vec<SinkWriter>>,
+    stop: Arc<watch::Sender<bool>>,
+}
+
+impl Sinks {
+    /// Starts one writer task per sink.
+    pub fn spawn(
+        sinks: Vec<Arc<dyn Sink>>,
+        config: &WriterConfig,
+    ) -> anyhow::Result<(Self, Vec<JoinHandle<()>>)> {
+        std::fs::create_dir_all(&config.spool_dir)
+            .with_context(|| format!("failed to create {}", config.spool_dir.display()))?;
+        let (stop, stopped) = watch::channel(false);
+        let (writers, tasks) = sinks
+            .into_iter()
+            .map(|sink| SinkWriter::spawn(sink, config.clone(), stopped.clone()))
+            .unzip();
+        Ok((
+            Self {
+                writers: Arc::new(writers),
+                stop: Arc::new(stop),
+            },
+            tasks,
+        ))
+    }
+
+    /// Tells every writer task to flush what it has buffered and exit.
+    /// Points written afterwards go to the spool.
+    pub fn close(&self) {
+        self.stop.send_replace(true);
+    }
+
+    /// Hands `point` to every sink. The receipt tells when all of them have
+    /// settled it, see [`Sinks::settled`].
+    pub fn write(&self, point: Point) -> Receipt {
+        let Some((last, rest)) = self.writers.split_last() else {
+            return Receipt::default();
+        };
+        let mut receipt = Vec::with_capacity(self.writers.len());
+        for writer in rest {
+            receipt.push(writer.write(point.clone()));
+        }
+        receipt.push(last.write(point));
+        Receipt(receipt)
+    }
+
+    /// Whether every point behind `receipt` was written by its sink, spooled
+    /// to disk or rejected as unwritable.
+    pub fn settled(&self, receipt: &Receipt) -> bool {
+        receipt
+            .0
+            .iter()
+            .zip(self.writers.iter())
+            .all(|(seq, writer)| writer.settled.load(Ordering::Acquire) >= *seq)
+    }
+
+    pub fn writers(&self) -> &[SinkWriter] {
+        &self.writers
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SinkWriter> {
+        self.writers.iter().find(|writer| writer.name() == name)
+    }
+}
+
+/// Per sink, the sequence number of the last point a write handed to its
+/// channel; 0 when nothing is waiting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Receipt(Vec<u64>);
+
+impl Receipt {
+    /// Covers the points of both receipts.
+    pub fn merge(&mut self, other: Receipt) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (seq, other) in self.0.iter_mut().zip(other.0) {
+            *seq = (*seq).max(other);
+        }
+    }
+}
+
+/// Warnings about overflowing points are logged at most this often.
+const OVERFLOW_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Cheap to clone handle for one sink. Writing never waits on the sink or the
+/// disk: when the channel is full the point waits in a bounded overflow buffer
+/// that the writer task spools in one append on its next flush.
+#[derive(Clone)]
+pub struct SinkWriter {
+    name: String,
+    tx: mpsc::Sender<(u64, Point)>,
+    /// Next sequence number; locked across the send so that sequence order
+    /// is channel order.
+    next_seq: Arc<Mutex<u64>>,
+    /// Highest sequence number the writer task is done with.
+    settled: Arc<AtomicU64>,
+    spool: Arc<Spool>,
+    overflow: Arc<Overflow>,
+    paused: Arc<AtomicBool>,
+    capacity: usize,
+}
+
+impl SinkWriter {
+    /// Starts the writer task. It runs until every handle is dropped or
+    /// `stopped` turns true, then flushes what is left and exits.
+    pub fn spawn(
+        sink: Arc<dyn Sink>,
+        config: WriterConfig,
+        stopped: watch::Receiver<bool>,
+    ) -> (Self, JoinHandle<()>) {
+        let name = sink.name().to_string();
+        let (tx, rx) = mpsc::channel(config.channel_capacity);
+        let spool = Arc::new(Spool::new(config.spool_dir.join(format!("{name}.ndjson"))));
+        let overflow = Arc::new(Overflow::new(config.channel_capacity));
+        let paused = Arc::new(AtomicBool::new(false));
+        let settled = Arc::new(AtomicU64::new(0));
+        let handle = Self {
+            name,
+            tx,
+            next_seq: Arc::new(Mutex::new(1)),
+            settled: settled.clone(),
+            spool: spool.clone(),
+            overflow: overflow.clone(),
+            paused: paused.clone(),
+            capacity: config.channel_capacity,
+        };
+        let task = WriterTask {
+            sink,
+            config,
+            spool,
+            overflow,
+            paused,
+            settled,
+        };
+        (handle, tokio::spawn(task.run(rx, stopped)))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the point's sequence number, or 0 when it was spooled on the
+    /// spot and is already settled. That only happens once the writer task
+    /// has stopped or the overflow buffer is full as well.
+    pub fn write(&self, point: Point) -> u64 {
+        let mut next_seq = self.next_seq.lock().unwrap();
+        let seq = *next_seq;
+        let point = match self.tx.try_send((seq, point)) {
+            Ok(()) => {
+                *next_seq += 1;
+                return seq;
+            }
+            // Buffered under the `next_seq` lock, so the task sees it before
+            // any later point and spools it before settling past it.
+            Err(mpsc::error::TrySendError::Full((_, point))) => match self.overflow.push(seq, point) {
+                Ok(()) => {
+                    *next_seq += 1;
+                    self.overflow.warn(&self.name, "writer channel full, spooling points");
+                    return seq;
+                }
+                Err(point) => point,
+            },
+            Err(mpsc::error::TrySendError::Closed((_, point))) => point,
+        };
+        drop(next_seq);
+        self.overflow.warn(&self.name, "writer stopped or overflow full, spooling points inline");
+        self.spool_point(point);
+        0
+    }
+
+    fn spool_point(&self, point: Point) {
+        let result = render(&[point]).and_then(|lines| self.spool.append(&lines));
+        if let Err(error) = result {
+            error!(sink = %self.name, ?error, "failed to spool point");
+        }
+    }
+
+    /// While paused, batches go to the spool instead of the sink; resuming
+    /// replays them on the next flush tick.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+        info!(sink = %self.name, paused, "writer paused state changed");
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Points waiting in the channel and the overflow buffer.
+    pub fn queue_depth(&self) -> usize {
+        self.capacity - self.tx.capacity() + self.overflow.len()
+    }
+
+    /// Points waiting in the on-disk spool.
+    pub fn spooled(&self) -> usize {
+        self.spool.len()
+    }
+}
+
+struct WriterTask {
+    sink: Arc<dyn Sink>,
+    config: WriterConfig,
+    spool: Arc<Spool>,
+    overflow: Arc<Overflow>,
+    paused: Arc<AtomicBool>,
+    settled: Arc<AtomicU64>,
+}
+
+/// Points waiting for the next flush and the sequence number of the last.
+#[derive(Default)]
+struct Batch {
+    points: Vec<Point>,
+    last_seq: u64,
+}
+
+impl Batch {
+    fn push(&mut self, (seq, point): (u64, Point)) {
+        self.points.push(point);
+        self.last_seq = seq;
+    }
+}
+
+impl WriterTask {
+    async fn run(self, mut rx: mpsc::Receiver<(u64, Point)>, mut stopped: watch::Receiver<bool>) {
+        let mut batch = Batch::default();
+        let mut interval = tokio::time::interval(self.config.flush_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                point = rx.recv() => match point {
+                    Some(point) => {
+                        batch.push(point);
+                        if batch.points.len() >= self.config.batch_size {
+                            self.flush(&mut batch).await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = interval.tick() => {
+                    self.flush(&mut batch).await;
+                    self.drain_spool().await;
+                }
+                _ = stopped.wait_for(|stopped| *stopped) => break,
+            }
+        }
+
+        // Take whatever was queued before the channel closed.
+        rx.close();
+        while let Some(point) = rx.recv().await {
+            batch.push(point);
+            if batch.points.len() >= self.config.batch_size {
+                self.flush(&mut batch).await;
+            }
+        }
+        self.flush(&mut batch).await;
+        info!(sink = self.sink.name(), spooled = self.spool.len(), "writer stopped");
+    }
+
+    /// Spools the overflow buffer, then writes or spools `batch`; either way
+    /// their points count as settled afterwards.
+    async fn flush(&self, batch: &mut Batch) {
+        // Overflowed points can be older than the batch, so they go to disk
+        // before the batch settles past them.
+        let overflow_seq = self.spool_overflow();
+        let Batch { points, last_seq } = std::mem::take(batch);
+        if !points.is_empty() {
+            self.write_or_spool(&points).await;
+        }
+        self.settled.fetch_max(last_seq.max(overflow_seq), Ordering::Release);
+    }
+
+    /// Appends the whole overflow buffer with a single sync. Returns the
+    /// highest sequence number spooled, or 0 when there was nothing.
+    fn spool_overflow(&self) -> u64 {
+        let overflow = self.overflow.take();
+        let Some(&(last_seq, _)) = overflow.last() else {
+            return 0;
+        };
+        let points: Vec<Point> = overflow.into_iter().map(|(_, point)| point).collect();
+        self.spool_batch(&points);
+        last_seq
+    }
+
+    async fn write_or_spool(&self, points: &[Point]) {
+        if self.paused.load(Ordering::Relaxed) {
+            self.spool_batch(points);
+            return;
+        }
+        match self.send_with_retry(points).await {
+            Ok(()) => {}
+            Err(SinkError::Retryable(error)) => {
+                warn!(sink = self.sink.name(), points = points.len(), ?error, "spooling batch");
+                self.spool_batch(points);
+            }
+            Err(SinkError::Fatal(error)) => {
+                error!(sink = self.sink.name(), points = points.len(), ?error, "sink rejected batch");
+            }
+        }
+    }
+
+    fn spool_batch(&self, points: &[Point]) {
+        let result = render(points).and_then(|lines| self.spool.append(&lines));
+        if let Err(error) = result {
+            error!(sink = self.sink.name(), ?error, "failed to spool batch");
+        }
+    }
+
+    /// Replays spooled points once the sink accepts writes again, one batch
+    /// at a time, oldest first.
+    async fn drain_spool(&self) {
+        while self.spool.len() > 0 && !self.paused.load(Ordering::Relaxed) {
+            let (lines, end) = match self.spool.read(self.config.batch_size) {
+                Ok(read) => read,
+                Err(error) => {
+                    error!(sink = self.sink.name(), ?error, "failed to read spool");
+                    return;
+                }
+            };
+            if lines.is_empty() {
+                return;
+            }
+            let points = lines
+                .iter()
+                .filter_map(|line| match serde_json::from_str::<Point>(line) {
+                    Ok(point) => Some(point),
+                    Err(error) => {
+                        warn!(sink = self.sink.name(), ?error, "dropping unreadable spool line");
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            match self.write(&points).await {
+                Ok(()) => {}
+                Err(SinkError::Retryable(error)) => {
+                    warn!(sink = self.sink.name(), ?error, "sink still unavailable, keeping spool");
+                    return;
+                }
+                Err(SinkError::Fatal(error)) => {
+                    error!(sink = self.sink.name(), points = points.len(), ?error, "sink rejected spooled points");
+                }
+            }
+            if let Err(error) = self.spool.consume(end, lines.len()) {
+                error!(sink = self.sink.name(), ?error, "failed to advance spool");
+                return;
+            }
+        }
+    }
+
+    /// One `Sink::write`, recorded in the sink metrics.
+    async fn write(&self, points: &[Point]) -> Result<(), SinkError> {
+        let name = self.sink.name();
+        metrics::SINK_BATCH_SIZE
+            .with_label_values(&[name])
+            .observe(points.len() as f64);
+        let timer = metrics::SINK_WRITE_SECONDS.with_label_values(&[name]).start_timer();
+        let result = self.sink.write(points).await;
+        timer.observe_duration();
+        let kind = match &result {
+            Ok(()) => return result,
+            Err(SinkError::Retryable(_)) => "retryable",
+            Err(SinkError::Fatal(_)) => "fatal",
+        };
+        metrics::SINK_WRITE_ERRORS.with_label_values(&[name, kind]).inc();
+        result
+    }
+
+    async fn send_with_retry(&self, points: &[Point]) -> Result<(), SinkError> {
+        let mut backoff = Backoff::new(Duration::from_millis(200), Duration::from_secs(5));
+        let mut attempt = 1;
+        loop {
+            match self.write(points).await {
+                Ok(()) => return Ok(()),
+                Err(SinkError::Retryable(error)) if attempt < self.config.max_attempts => {
+                    warn!(sink = self.sink.name(), attempt, ?error, "write attempt failed");
+                    tokio::time::sleep(backoff.next_delay()).await;
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Points that found the writer channel full, in sequence order.
+struct Overflow {
+    points: Mutex<Vec<(u64, Point)>>,
+    capacity: usize,
+    /// Points counted since the last warning, and when that was logged.
+    warning: Mutex<(usize, Option<Instant>)>,
+}
+
+impl Overflow {
+    fn new(capacity: usize) -> Self {
+        Self {
+            points: Mutex::new(Vec::new()),
+            capacity,
+            warning: Mutex::new((0, None)),
+        }
+    }
+
+    /// Hands `point` back when the buffer is full.
+    fn push(&self, seq: u64, point: Point) -> Result<(), Point> {
+        let mut points = self.points.lock().unwrap();
+        if points.len() >= self.capacity {
+            return Err(point);
+        }
+        points.push((seq, point));
+        Ok(())
+    }
+
+    fn take(&self) -> Vec<(u64, Point)> {
+        std::mem::take(&mut *self.points.lock().unwrap())
+    }
+
+    fn len(&self) -> usize {
+        self.points.lock().unwrap().len()
+    }
+
+    /// Counts one point and logs `message` with the count at most once per
+    /// [`OVERFLOW_WARNING_INTERVAL`].
+    fn warn(&self, sink: &str, message: &str) {
+        let mut warning = self.warning.lock().unwrap();
+        warning.0 += 1;
+        if warning.1.map_or(true, |logged| logged.elapsed() >= OVERFLOW_WARNING_INTERVAL) {
+            warn!(sink, points = warning.0, "{message}");
+            *warning = (0, Some(Instant::now()));
+        }
+    }
+}
+
+/// Spool lines are the points as NDJSON, independent of the sink format.
+fn render(points: &[Point]) -> anyhow::Result<String> {
+    let lines = points
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(lines.join("\n"))
+}
+
+/// Append-only NDJSON file for batches a sink did not accept. Draining
+/// reads forward from an offset kept in a sidecar file instead of rewriting
+/// the spool, and the file is removed once everything in it is drained.
+/// A crash between a write and saving the offset replays that batch, which
+/// the sinks dedupe by point ID.
+struct Spool {
+    path: PathBuf,
+    offset_path: PathBuf,
+    /// Lines past the offset.
+    lines: AtomicUsize,
+    /// Byte offset of the first line not yet drained.
+    offset: Mutex<u64>,
+}
+
+impl Spool {
+    fn new(path: PathBuf) -> Self {
+        let offset_path = path.with_extension("offset");
+        let offset = std::fs::read_to_string(&offset_path)
+            .ok()
+            .and_then(|offset| offset.trim().parse().ok())
+            .unwrap_or(0);
+        let lines = File::open(&path)
+            .and_then(|file| {
+                let mut reader = BufReader::new(file);
+                reader.seek(SeekFrom::Start(offset))?;
+                Ok(reader.lines().count())
+            })
+            .unwrap_or(0);
+        Self {
+            path,
+            offset_path,
+            lines: AtomicUsize::new(lines),
+            offset: Mutex::new(offset),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    /// Synced before returning, so spooled points survive a crash.
+    fn append(&self, lines: &str) -> anyhow::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let _offset = self.offset.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open {}", self.path.display()))?;
+        writeln!(file, "{lines}")?;
+        file.sync_data()
+            .with_context(|| format!("failed to sync {}", self.path.display()))?;
+        self.lines.fetch_add(lines.lines().count(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Up to `max` lines past the offset, and the offset just after them.
+    fn read(&self, max: usize) -> anyhow::Result<(Vec<String>, u64)> {
+        let offset = self.offset.lock().unwrap();
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok((Vec::new(), *offset)),
+            Err(error) => return Err(error.into()),
+        };
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(*offset))?;
+        let mut lines = Vec::new();
+        let mut end = *offset;
+        while lines.len() < max {
+            let mut line = String::new();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            end += read as u64;
+            lines.push(line.trim_end().to_string());
+        }
+        Ok((lines, end))
+    }
+
+    /// Marks the `lines` before `end` as drained.
+    fn consume(&self, end: u64, lines: usize) -> anyhow::Result<()> {
+        let mut offset = self.offset.lock().unwrap();
+        let size = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(error) if error.kind() == ErrorKind::NotFound => 0,
+            Err(error) => return Err(error.into()),
+        };
+        if end >= size {
+            // Nothing was appended since the read: start over empty.
+            for path in [&self.path, &self.offset_path] {
+                std::fs::remove_file(path).or_else(|error| match error.kind() {
+                    ErrorKind::NotFound => Ok(()),
+                    _ => Err(error),
+                })?;
+            }
+            *offset = 0;
+            self.lines.store(0, Ordering::Relaxed);
+        } else {
+            std::fs::write(&self.offset_path, end.to_string())
+                .with_context(|| format!("failed to write {}", self.offset_path.display()))?;
+            *offset = end;
+            self.lines.fetch_sub(lines.min(self.len()), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use chrono::{TimeZone, Utc};
+
+    fn point(slot: u64) -> Point {
+        let time = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        Point::new("slots", slot.to_string(), time).field("slot", slot)
+    }
+
+    fn spool_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("writer-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{name}.ndjson"));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path.with_extension("offset"));
+        path
+    }
+
+    /// Fails every write with the given error and counts the attempts.
+    struct FailingSink {
+        retryable: bool,
+        attempts: Mutex<u32>,
+    }
+
+    #[async_trait]
+    impl Sink for FailingSink {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        async fn write(&self, _points: &[Point]) -> Result<(), SinkError> {
+            *self.attempts.lock().unwrap() += 1;
+            let error = anyhow::anyhow!("unavailable");
+            Err(if self.retryable { SinkError::Retryable(error) } else { SinkError::Fatal(error) })
+        }
+    }
+
+    fn task(sink: Arc<FailingSink>, path: PathBuf) -> WriterTask {
+        WriterTask {
+            sink,
+            config: WriterConfig::default(),
+            spool: Arc::new(Spool::new(path)),
+            overflow: Arc::new(Overflow::new(10)),
+            paused: Arc::new(AtomicBool::new(false)),
+            settled: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    #[test]
+    fn spool_resumes_from_the_saved_offset() {
+        let path = spool_path("resume");
+        let spool = Spool::new(path.clone());
+        let points: Vec<Point> = (1..=5).map(point).collect();
+        spool.append(&render(&points).unwrap()).unwrap();
+        assert_eq!(spool.len(), 5);
+
+        let (lines, end) = spool.read(2).unwrap();
+        assert_eq!(lines, render(&points[..2]).unwrap().lines().collect::<Vec<_>>());
+        spool.consume(end, lines.len()).unwrap();
+        assert_eq!(spool.len(), 3);
+        drop(spool);
+
+        // A restart picks up after the consumed lines.
+        let spool = Spool::new(path.clone());
+        assert_eq!(spool.len(), 3);
+        let (lines, end) = spool.read(10).unwrap();
+        assert_eq!(lines, render(&points[2..]).unwrap().lines().collect::<Vec<_>>());
+
+        // Draining everything removes both files.
+        spool.consume(end, lines.len()).unwrap();
+        assert_eq!(spool.len(), 0);
+        assert!(!path.exists());
+        assert!(!path.with_extension("offset").exists());
+        assert_eq!(spool.read(10).unwrap().0, Vec::<String>::new());
+    }
+
+    #[test]
+    fn spool_keeps_lines_appended_after_a_read() {
+        let path = spool_path("append-after-read");
+        let spool = Spool::new(path.clone());
+        spool.append(&render(&[point(1)]).unwrap()).unwrap();
+        let (lines, end) = spool.read(10).unwrap();
+        spool.append(&render(&[point(2)]).unwrap()).unwrap();
+        spool.consume(end, lines.len()).unwrap();
+
+        assert_eq!(spool.len(), 1);
+        assert_eq!(spool.read(10).unwrap().0, vec![render(&[point(2)]).unwrap()]);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("offset")).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retryable_errors_are_retried_then_spooled() {
+        let sink = Arc::new(FailingSink {
+            retryable: true,
+            attempts: Mutex::new(0),
+        });
+        let task = task(sink.clone(), spool_path("retryable"));
+        task.write_or_spool(&[point(1), point(2)]).await;
+
+        assert_eq!(*sink.attempts.lock().unwrap(), task.config.max_attempts);
+        assert_eq!(task.spool.len(), 2);
+        std::fs::remove_file(&task.spool.path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fatal_errors_are_dropped_after_one_attempt() {
+        let sink = Arc::new(FailingSink {
+            retryable: false,
+            attempts: Mutex::new(0),
+        });
+        let task = task(sink.clone(), spool_path("fatal"));
+        task.write_or_spool(&[point(1), point(2)]).await;
+
+        assert_eq!(*sink.attempts.lock().unwrap(), 1);
+        assert_eq!(task.spool.len(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flush_spools_the_overflow_and_settles_past_it() {
+        let sink = Arc::new(FailingSink {
+            retryable: false,
+            attempts: Mutex::new(0),
+        });
+        let task = task(sink.clone(), spool_path("overflow"));
+        task.overflow.push(3, point(3)).unwrap();
+        task.overflow.push(4, point(4)).unwrap();
+        let mut batch = Batch::default();
+        batch.push((2, point(2)));
+        task.flush(&mut batch).await;
+
+        assert_eq!(task.overflow.len(), 0);
+        assert_eq!(task.spool.len(), 2);
+        assert_eq!(task.settled.load(Ordering::Acquire), 4);
+        std::fs::remove_file(&task.spool.path).unwrap();
+    }
+
+    #[test]
+    fn overflow_hands_points_back_when_full() {
+        let overflow = Overflow::new(1);
+        assert!(overflow.push(1, point(1)).is_ok());
+        assert_eq!(overflow.push(2, point(2)), Err(point(2)));
+        assert_eq!(overflow.take(), vec![(1, point(1))]);
+    }
+}